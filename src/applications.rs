@@ -0,0 +1,292 @@
+use fetish_lib::everything::*;
+use crate::bindings::*;
+use crate::parsers::*;
+use crate::expression::*;
+use crate::typecheck::*;
+use crate::error::*;
+use crate::terms::*;
+
+pub const DEFAULT_PAGE_SIZE : usize = 20;
+
+///Which entries of an application table to list, and which page of them to show
+pub struct ApplicationQuery {
+    pub func_type_id : TypeId,
+    pub maybe_func_ptr : Option<TermPointer>,
+    pub maybe_arg_ref : Option<TermReference>,
+    ///Starts from 1
+    pub page : usize,
+    pub page_size : usize
+}
+
+///Splits on whitespace, except within brackets, so that vector literals stay in one piece
+pub fn split_arguments(text : &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth : usize = 0;
+    let mut maybe_start : Option<usize> = Option::None;
+    for (ind, c) in text.char_indices() {
+        match (c) {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if (c.is_whitespace() && depth == 0) {
+            if let Option::Some(start) = maybe_start.take() {
+                result.push(&text[start..ind]);
+            }
+        } else if (maybe_start.is_none()) {
+            maybe_start = Option::Some(ind);
+        }
+    }
+    if let Option::Some(start) = maybe_start {
+        result.push(&text[start..]);
+    }
+    result
+}
+
+type ResolveTermFunc<'a> = dyn Fn(&str) -> Result<(TermReference, TypeId), InspectorError> + 'a;
+
+fn parse_count(key : &str, text : &str) -> Result<usize, InspectorError> {
+    match (text.parse::<usize>()) {
+        Result::Ok(count) if count > 0 => Result::Ok(count),
+        _ => Result::Err(InspectorError::Command(format!("{} must be a positive integer, but was {}", key, text)))
+    }
+}
+
+impl ApplicationQuery {
+    ///Matches every application of functions of the given type
+    pub fn all(func_type_id : TypeId) -> ApplicationQuery {
+        ApplicationQuery {
+            func_type_id,
+            maybe_func_ptr : Option::None,
+            maybe_arg_ref : Option::None,
+            page : 1,
+            page_size : DEFAULT_PAGE_SIZE
+        }
+    }
+
+    ///Parses [type] followed by any of func=[term], arg=[term], page=[n], page_size=[n]
+    pub fn parse(text : &str, bindings : &Bindings, zelf : &InterpreterAndEmbedderState) -> Result<ApplicationQuery, InspectorError> {
+        ApplicationQuery::parse_with(text, zelf.get_context(), &|term_text| {
+            let term_ref = parse_term_ref(term_text, bindings, zelf)?;
+            let type_id = zelf.check_expression(&Expression::Ref(term_ref.clone()))?;
+            Result::Ok((term_ref, type_id))
+        })
+    }
+
+    ///Like parse, but with terms resolved [to themselves and their types] by the given function
+    fn parse_with(text : &str, types : &dyn DescribesTypes,
+                  resolve_term : &ResolveTermFunc<'_>) -> Result<ApplicationQuery, InspectorError> {
+        let arguments = split_arguments(text);
+        if (arguments.is_empty()) {
+            return Result::Err(InspectorError::Command("Expected a function type number".to_owned()));
+        }
+        let func_type_id = arguments[0].parse::<usize>()
+                           .map_err(|err| InspectorError::Command(format!("Unable to parse type number from {}, due to error: {}",
+                                                                          arguments[0], err)))?;
+        check_type_exists(types, func_type_id)?;
+        let expected_arg_type_id = match (types.get_type_kind(func_type_id)) {
+            Type::FuncType(arg_type_id, _) => arg_type_id,
+            Type::VecType(_) => {
+                return Result::Err(InspectorError::type_error(format!("{} is a vector type, so it has no applications",
+                                                                format_type(types, func_type_id))));
+            }
+        };
+
+        let mut result = ApplicationQuery::all(func_type_id);
+        for argument in arguments[1..].iter() {
+            let (key, value) = argument.split_once('=')
+                               .ok_or_else(|| InspectorError::Command(format!("Option {} does not have the format [key]=[value]", argument)))?;
+            match (key) {
+                "func" => {
+                    match (resolve_term(value)?) {
+                        (TermReference::FuncRef(func_ptr), _) if func_ptr.type_id == func_type_id => {
+                            result.maybe_func_ptr = Option::Some(func_ptr);
+                        },
+                        _ => {
+                            return Result::Err(InspectorError::type_error(format!("{} is not a term of type {}",
+                                                                            value, format_type(types, func_type_id))));
+                        }
+                    }
+                },
+                "arg" => {
+                    let (arg_ref, arg_type_id) = resolve_term(value)?;
+                    if (arg_type_id != expected_arg_type_id) {
+                        return Result::Err(InspectorError::type_error(format!("{} has type {}, but functions of type {} take {}",
+                                                                        value, format_type(types, arg_type_id),
+                                                                        format_type(types, func_type_id),
+                                                                        format_type(types, expected_arg_type_id))));
+                    }
+                    result.maybe_arg_ref = Option::Some(arg_ref);
+                },
+                "page" => result.page = parse_count(key, value)?,
+                "page_size" => result.page_size = parse_count(key, value)?,
+                _ => {
+                    return Result::Err(InspectorError::Command(format!("Unknown option {} [expected func, arg, page, or page_size]", key)));
+                }
+            }
+        }
+        Result::Ok(result)
+    }
+}
+
+///All recorded applications matching the query's filters [ignoring paging]
+pub fn find_app_results(state : &InterpreterState, ctxt : &Context,
+                        query : &ApplicationQuery) -> Vec<TermApplicationResult> {
+    let application_table = match (state.application_tables.get(&query.func_type_id)) {
+        Option::None => {
+            return Vec::new();
+        },
+        Option::Some(application_table) => application_table
+    };
+    let mut app_results = match (&query.maybe_func_ptr, &query.maybe_arg_ref) {
+        (Option::Some(func_ptr), _) => application_table.get_app_results_with_func(*func_ptr),
+        (Option::None, Option::Some(arg_ref)) => application_table.get_app_results_with_arg(arg_ref),
+        (Option::None, Option::None) => {
+            get_func_ptrs(state, ctxt, query.func_type_id).into_iter()
+                .flat_map(|func_ptr| application_table.get_app_results_with_func(func_ptr))
+                .collect()
+        }
+    };
+    if let Option::Some(arg_ref) = &query.maybe_arg_ref {
+        app_results.retain(|app_result| &app_result.get_arg_ref() == arg_ref);
+    }
+    app_results
+}
+
+pub fn format_app_result(state : &InterpreterState, app_result : &TermApplicationResult) -> String {
+    let func_ptr = app_result.get_func_ptr();
+    let arg_ref = app_result.get_arg_ref();
+    let ret_ref = app_result.get_ret_ref();
+    format!("({} {}) -> {}    [({} {}) -> {}]",
+            format_term_ptr(&func_ptr), format_term_ref(&arg_ref), format_term_ref(&ret_ref),
+            func_ptr.display(state), arg_ref.display(state), ret_ref.display(state))
+}
+
+///For the given [1-based] page of a non-empty list of results, returns that page [or the last
+///page, if it's past the end], the number of pages, and the range of results on the page
+fn get_page_bounds(num_results : usize, page : usize, page_size : usize) -> (usize, usize, usize, usize) {
+    let num_pages = num_results.div_ceil(page_size);
+    let page = page.min(num_pages);
+    let start = (page - 1) * page_size;
+    let end = (start + page_size).min(num_results);
+    (page, num_pages, start, end)
+}
+
+///Renders the page of matching applications which the query asks for, with a header saying where it is
+pub fn render_app_results(state : &InterpreterState, ctxt : &Context, query : &ApplicationQuery) -> String {
+    let app_results = find_app_results(state, ctxt, query);
+    if (app_results.is_empty()) {
+        return format!("No recorded applications for {}", format_type(ctxt, query.func_type_id));
+    }
+    let (page, num_pages, start, end) = get_page_bounds(app_results.len(), query.page, query.page_size);
+
+    let mut lines = Vec::new();
+    lines.push(format!("Applications {}-{} of {} [page {} of {}]", start + 1, end, app_results.len(), page, num_pages));
+    for app_result in app_results[start..end].iter() {
+        lines.push(format_app_result(state, app_result));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noisy_float::prelude::*;
+
+    struct TestTypes;
+
+    impl DescribesTypes for TestTypes {
+        fn get_num_types(&self) -> usize {
+            2
+        }
+        fn get_type_kind(&self, type_id : TypeId) -> Type {
+            [Type::VecType(2), Type::FuncType(0, 0)][type_id].clone()
+        }
+        fn display_type(&self, type_id : TypeId) -> String {
+            ["R^2", "R^2 -> R^2"][type_id].to_owned()
+        }
+        fn get_num_primitive_terms(&self, _type_id : TypeId) -> usize {
+            1
+        }
+    }
+
+    //Resolves #1p0 and #0[1, 2], and nothing else
+    fn resolve_term(term_text : &str) -> Result<(TermReference, TypeId), InspectorError> {
+        match (term_text) {
+            "#1p0" => Result::Ok((TermReference::FuncRef(TermPointer {
+                                      type_id : 1,
+                                      index : TermIndex::Primitive(0)
+                                  }), 1)),
+            "#0[1,2]" => Result::Ok((TermReference::VecRef(0, Array1::from(vec![r32(1.0), r32(2.0)])), 0)),
+            _ => Result::Err(InspectorError::resolution(format!("Unknown term {}", term_text)))
+        }
+    }
+
+    fn parse(text : &str) -> Result<ApplicationQuery, InspectorError> {
+        ApplicationQuery::parse_with(text, &TestTypes, &resolve_term)
+    }
+
+    fn command_error(text : &str) -> String {
+        match (parse(text)) {
+            Result::Err(InspectorError::Command(message)) => message,
+            Result::Err(err) => panic!("Expected a command error for {:?}, but got {}", text, err),
+            Result::Ok(_) => panic!("Expected {:?} not to parse", text)
+        }
+    }
+
+    #[test]
+    fn split_arguments_keeps_brackets_together() {
+        assert_eq!(split_arguments("  1 func=#1p0\targ=#0[1, 2]  page=2 "), vec!["1", "func=#1p0", "arg=#0[1, 2]", "page=2"]);
+        assert_eq!(split_arguments("arg=(f (g x)) y"), vec!["arg=(f (g x))", "y"]);
+        //Unbalanced closing brackets don't stop later whitespace from splitting
+        assert_eq!(split_arguments("a] b"), vec!["a]", "b"]);
+        assert!(split_arguments("   ").is_empty());
+    }
+
+    #[test]
+    fn parses_filters_and_paging() {
+        let query = parse("1 func=#1p0 arg=#0[1,2] page=3 page_size=5").ok().unwrap();
+        assert_eq!(query.func_type_id, 1);
+        assert_eq!(query.maybe_func_ptr.map(|func_ptr| func_ptr.index), Option::Some(TermIndex::Primitive(0)));
+        assert!(query.maybe_arg_ref.is_some());
+        assert_eq!((query.page, query.page_size), (3, 5));
+
+        let query = parse("1").ok().unwrap();
+        assert_eq!((query.page, query.page_size), (1, DEFAULT_PAGE_SIZE));
+    }
+
+    #[test]
+    fn rejects_malformed_paging() {
+        assert_eq!(command_error("1 page=0"), "page must be a positive integer, but was 0");
+        assert_eq!(command_error("1 page=two"), "page must be a positive integer, but was two");
+        assert_eq!(command_error("1 page_size=0"), "page_size must be a positive integer, but was 0");
+        assert_eq!(command_error("1 page_size=-3"), "page_size must be a positive integer, but was -3");
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_options() {
+        assert_eq!(command_error("1 sort=asc"), "Unknown option sort [expected func, arg, page, or page_size]");
+        assert_eq!(command_error("1 page"), "Option page does not have the format [key]=[value]");
+        assert!(command_error("").starts_with("Expected a function type number"));
+        assert!(command_error("one").starts_with("Unable to parse type number from one"));
+    }
+
+    #[test]
+    fn rejects_ill_typed_queries() {
+        assert!(matches!(parse("0"), Result::Err(InspectorError::Type { .. })));
+        assert!(matches!(parse("2"), Result::Err(InspectorError::Type { .. })));
+        assert!(matches!(parse("1 func=#0[1,2]"), Result::Err(InspectorError::Type { .. })));
+        assert!(matches!(parse("1 arg=#1p0"), Result::Err(InspectorError::Type { .. })));
+        assert!(matches!(parse("1 arg=#1p7"), Result::Err(InspectorError::Resolution { .. })));
+    }
+
+    #[test]
+    fn page_bounds_cover_the_results() {
+        assert_eq!(get_page_bounds(45, 1, 20), (1, 3, 0, 20));
+        assert_eq!(get_page_bounds(45, 3, 20), (3, 3, 40, 45));
+        assert_eq!(get_page_bounds(40, 2, 20), (2, 2, 20, 40));
+        //Pages past the end show the last page
+        assert_eq!(get_page_bounds(45, 9, 20), (3, 3, 40, 45));
+        assert_eq!(get_page_bounds(1, 1, 20), (1, 1, 0, 1));
+    }
+}
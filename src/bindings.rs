@@ -1,6 +1,7 @@
 use fetish_lib::everything::*;
 use std::collections::HashMap;
 use crate::expression::*;
+use crate::error::*;
 
 pub struct Bindings {
     bindings : HashMap<String, TermReference>
@@ -13,15 +14,34 @@ impl Bindings {
     pub fn write(&mut self, identifier : String, term_ref : TermReference) {
         self.bindings.insert(identifier, term_ref);
     }
-    pub fn lookup<'a>(&self, identifier : &'a str) -> Result<TermReference, String> {
+    pub fn get_identifiers(&self) -> Vec<String> {
+        let mut result : Vec<String> = self.bindings.keys().cloned().collect();
+        result.sort();
+        result
+    }
+    pub fn lookup<'a>(&self, identifier : &'a str) -> Result<TermReference, InspectorError> {
         let identifier_as_string = identifier.to_string();
         let maybe_result = self.bindings.get(&identifier_as_string);
         match (maybe_result) {
-            Option::None => Result::Err(format!("No identifier named {} in scope", identifier)),
+            Option::None => Result::Err(InspectorError::resolution(format!("No identifier named {} in scope", identifier))),
             Option::Some(result) => Result::Ok(result.clone())
         }
     }
     
+    ///Json-izes the identifier-to-term mapping
+    pub fn serialize(&self) -> Result<Vec<u8>, InspectorError> {
+        serde_json::to_vec_pretty(&self.bindings)
+                   .map_err(|err| InspectorError::Serialization(format!("Bindings: {}", err)))
+    }
+
+    pub fn deserialize(bytes : &[u8]) -> Result<Bindings, InspectorError> {
+        let bindings = serde_json::from_slice::<HashMap<String, TermReference>>(bytes)
+                       .map_err(|err| InspectorError::Serialization(format!("Bindings: {}", err)))?;
+        Result::Ok(Bindings {
+            bindings
+        })
+    }
+
     pub fn new() -> Bindings {
         Bindings {
             bindings : HashMap::new()
@@ -1,9 +1,23 @@
 use std::fs;
+use std::sync::Arc;
 use crate::bindings::*;
 use crate::state::*;
 use crate::parsers::*;
 use crate::expression::*;
+use crate::typecheck::*;
+use crate::history::*;
+use crate::script::*;
+use crate::error::*;
+use crate::applications::*;
+use crate::terms::*;
+use crate::statistics::*;
+use crate::simulate::*;
+use crate::qmc::*;
+use crate::compare::*;
+use crate::fit::*;
+use crate::neighbors::*;
 use fetish_lib::everything::*;
+use rand::prelude::*;
 
 pub enum Command {
     Contextual(ContextualCommand),
@@ -11,59 +25,144 @@ pub enum Command {
     GenerateContextFromPath(String),
     LoadContextFromPath(String),
     UnloadContext,
+    LoadBindingsFromPath(String),
+    SaveBindingsToPath(String),
+    LoadSobolDirectionsFromPath(String),
+    Source(String),
+    History(String),
+    SetTrace(bool),
+    Seed(Option<u64>),
     Help
 }
 
+///How a simulate command should draw and report its samples
+pub struct SimulationOptions {
+    ///None for drawing a single sample and printing it as-is, rather than summarizing
+    pub maybe_num_samples : Option<usize>,
+    ///None for DEFAULT_QUANTILES
+    pub maybe_quantile_levels : Option<Vec<f64>>,
+    pub maybe_output_path : Option<String>,
+    ///Whether to simulate with each term's posterior mean, rather than a sample
+    pub at_mean : bool,
+    ///Whether each run should sample every distinct term only once, rather than
+    ///sampling each of its occurrences independently
+    pub consistent : bool,
+    pub strategy : SamplingStrategy
+}
+
+impl SimulationOptions {
+    pub fn new() -> SimulationOptions {
+        SimulationOptions {
+            maybe_num_samples : Option::None,
+            maybe_quantile_levels : Option::None,
+            maybe_output_path : Option::None,
+            at_mean : false,
+            consistent : false,
+            strategy : SamplingStrategy::Plain
+        }
+    }
+
+    pub fn get_quantile_levels(&self) -> &[f64] {
+        self.maybe_quantile_levels.as_deref().unwrap_or(DEFAULT_QUANTILES)
+    }
+}
+
+impl Default for SimulationOptions {
+    fn default() -> SimulationOptions {
+        SimulationOptions::new()
+    }
+}
+
 pub enum ContextualCommand {
     Let(String, String),
     Evaluate(String),
-    Simulate(String),
+    TraceEvaluate(String),
+    Simulate(SimulationOptions, String),
+    Compare(SimulationOptions, String),
+    FitReport(SimulationOptions),
+    TypeOf(String),
     ListTypes,
     UpdateModels,
     ListPrimitiveTerms(String),
+    ListTerms(String),
+    Show(String),
+    ListApplications(String),
+    Neighbors(String),
     SaveContextToPath(String),
     LoadModelsFromPath(String),
     SaveModelsToPath(String)
 }
 
 impl Command {
-    pub fn handle_command<'a>(self, glob_state : &mut GlobalState<'a>) {
+    pub fn handle_command<'a>(self, glob_state : &mut GlobalState<'a>) -> Result<(), InspectorError> {
         match (self) {
             Command::Contextual(context_command) => context_command.handle_command(glob_state),
-            Command::Parse(text) => handle_parse(text, &glob_state.bindings),
+            Command::Parse(text) => handle_parse(text, &*glob_state),
             Command::GenerateContextFromPath(path) => handle_generate_context(path, glob_state),
             Command::LoadContextFromPath(path) => handle_load_context(path, glob_state),
-            Command::UnloadContext => glob_state.unload_context(),
+            Command::UnloadContext => {
+                glob_state.unload_context();
+                Result::Ok(())
+            },
+            Command::LoadBindingsFromPath(path) => handle_load_bindings(path, &mut glob_state.bindings),
+            Command::SaveBindingsToPath(path) => handle_save_bindings(path, &glob_state.bindings),
+            Command::LoadSobolDirectionsFromPath(path) => handle_load_sobol_directions(path, glob_state),
+            Command::Source(path) => source_script(&path, glob_state),
+            Command::History(query) => handle_history(query, &glob_state.history),
+            Command::Seed(maybe_seed) => handle_seed(maybe_seed, glob_state),
+            Command::SetTrace(trace_evaluation) => {
+                glob_state.trace_evaluation = trace_evaluation;
+                Result::Ok(())
+            },
             Command::Help => handle_help()
         }
     }
 }
 
 impl ContextualCommand {
-    pub fn handle_command<'a>(self, glob_state : &mut GlobalState<'a>) {
+    pub fn handle_command<'a>(self, glob_state : &mut GlobalState<'a>) -> Result<(), InspectorError> {
         let bindings = &mut glob_state.bindings;
+        let trace_evaluation = glob_state.trace_evaluation;
+        let rng = &mut glob_state.rng;
+        let maybe_sobol_directions = &glob_state.maybe_sobol_directions;
         match (&mut glob_state.maybe_context_state) {
             Option::None => {
-                println!("This command may not be executed without a currently-loaded context");
+                Result::Err(InspectorError::NoContext)
             },
             Option::Some(context_state) => {
                 match (self) {
                     ContextualCommand::Let(var_text, expr_text)
-                                     => handle_let(var_text, expr_text, context_state, bindings),
+                                     => handle_let(var_text, expr_text, trace_evaluation, context_state, bindings),
                     ContextualCommand::Evaluate(expr_text)
-                                     => handle_evaluate(expr_text, context_state, bindings),
-                    ContextualCommand::Simulate(expr_text)
-                                     => handle_simulate(expr_text, context_state, &*bindings),
+                                     => handle_evaluate(expr_text, trace_evaluation, context_state, bindings),
+                    ContextualCommand::TraceEvaluate(expr_text)
+                                     => handle_evaluate(expr_text, true, context_state, bindings),
+                    ContextualCommand::Simulate(options, expr_text)
+                                     => handle_simulate(options, expr_text, context_state, &*bindings, maybe_sobol_directions, rng),
+                    ContextualCommand::Compare(options, expr_text)
+                                     => handle_compare(options, expr_text, context_state, &*bindings, maybe_sobol_directions, rng),
+                    ContextualCommand::FitReport(options)
+                                     => handle_fit_report(options, context_state, maybe_sobol_directions, rng),
+                    ContextualCommand::TypeOf(expr_text)
+                                     => handle_typeof(expr_text, context_state, &*bindings),
                     ContextualCommand::ListTypes
                                      => handle_list_types(&*context_state),
                     ContextualCommand::ListPrimitiveTerms(type_text)
                                      => handle_list_primitive_terms(type_text, &*context_state),
+                    ContextualCommand::ListTerms(type_text)
+                                     => handle_list_terms(type_text, context_state),
+                    ContextualCommand::Show(term_text)
+                                     => handle_show(term_text, context_state, &*bindings),
+                    ContextualCommand::ListApplications(query_text)
+                                     => handle_list_apps(query_text, context_state, &*bindings),
+                    ContextualCommand::Neighbors(query_text)
+                                     => handle_neighbors(query_text, context_state, &*bindings),
                     ContextualCommand::SaveContextToPath(path)
                                      => handle_save_context(path, &*context_state),
                     ContextualCommand::LoadModelsFromPath(path)
                                      => handle_load_models(path, context_state, bindings),
                     ContextualCommand::SaveModelsToPath(path)
-                                     => handle_save_models(path, &*context_state),
+                                     => handle_save_models(path, context_state),
                     ContextualCommand::UpdateModels
                                      => handle_update_models(context_state)
                 }
@@ -72,7 +171,7 @@ impl ContextualCommand {
     }
 }
 
-pub fn handle_help() {
+pub fn handle_help() -> Result<(), InspectorError> {
     println!("generate_context [path]: Generates a Context from the path to json-ized Params to generate it from");
     println!("load_context [path]: Loads a json-ized Context from the given path");
     println!("unload_context: Unloads the current Context");
@@ -82,232 +181,392 @@ pub fn handle_help() {
     println!("eval [expr] | evaluate [expr]: Evaluates the expression, and prints the result");
     println!("update_models: Updates the embeddings for all terms with respect to any newly-evaluated terms");
     println!("simulate [expr] | sim [expr]: Simulates the given expression [via a drawn sample], and prints the result");
-    println!("list_primitive_terms [type_num] | list_prim_terms [type_num]: Lists the primitive terms of the type with the given number");
+    println!("sim -n [count] -s [plain|antithetic|halton|sobol] [expr]: Simulates with the given sampling strategy, for lower-variance estimates of the mean. antithetic pairs each sample with its reflection about the posterior mean, while halton and sobol draw from low-discrepancy sequences mapped through each term's model. To do so, halton and sobol fix each model's row covariance at its expected value rather than drawing it, so their samples come from a narrower distribution than plain and antithetic sampling, and their std and quantiles understate the posterior's spread. sobol needs load_sobol_directions first. The est_var column is the estimated variance of the reported mean.");
+    println!("compare [-n count] [-q q1,q2,...] [-s strategy] [expr]: Evaluates the given vector-typed expression, and compares the result with count simulations of it [1000 by default], reporting the squared error of the simulated mean, per-dimension z-scores, and whether each dimension falls between the lowest and highest quantiles. Doesn't affect the next update_models, but any terms and applications which the evaluation creates are still added to the models, and so show up in later show, list_apps and neighbors.");
+    println!("fit_report [-n count] [-s strategy] [--consistent]: Simulates every recorded application with a vector result count times [100 by default], and reports the RMSE of the simulated means, the mean log predictive density [under a Gaussian fit to the samples], and the fraction of dimensions inside the central 50% and 90% predictive intervals, for each function type");
+    println!("sim --mean [expr]: Simulates the given expression with every term at its posterior mean, rather than a sample, which is deterministic");
+    println!("sim --consistent [expr]: Simulates the given expression drawing only one sample per distinct term, which is reused wherever the term occurs [by default, every occurrence is sampled independently]");
+    println!("sim -n [count] [-q q1,q2,...] [-o path] [expr]: Simulates the given expression count times, and prints the per-dimension mean, standard deviation and quantiles [0.05, 0.5 and 0.95 by default], optionally writing all samples to the given .csv or .npy path. Runs are simulated in parallel, in batches whose results are the same for a given seed regardless of the number of threads.");
+    println!("typeof [expr]: Prints the type of the given expression, without evaluating it");
+    println!("list_primitive_terms [type_num] | list_prim_terms [type_num]: Lists the primitive terms of the type with the given number, which may be referred to in expressions as @[name] or @[name]:[type_num]");
+    println!("list_terms [type_num]: Lists all primitive [p] and non-primitive [n] terms of the type with the given number");
+    println!("show [term]: Prints the given term's rendering, its type, and how many recorded applications it takes part in");
+    println!("list_apps [type_num] [func=term] [arg=term] [page=n] [page_size=n]: Lists the applications recorded for the function type with the given number, optionally only those of the given function or to the given argument");
+    println!("neighbors [-m cosine|euclidean] [term] [k]: Lists the k terms [10 by default] whose mean embeddings are nearest to the given term's, among terms of the same type, by cosine distance unless -m says otherwise");
     println!("save_context [path]: Saves the current Context, json-ized, to the given path");
     println!("load_models [path]: Loads the jsonized interpreter+embedder state from the given path");
     println!("save_models [path]: Saves the interpreter+embedder state as json to the given path");
+    println!("load_bindings [path]: Replaces all variable bindings with the json-ized bindings at the given path");
+    println!("save_bindings [path]: Saves all variable bindings as json to the given path");
+    println!("load_sobol_directions [path]: Loads Joe-Kuo direction numbers [such as new-joe-kuo-6.21201, from https://web.maths.unsw.edu.au/~fkuo/sobol/] for sobol sampling");
+    println!("source [path]: Runs each line of the given file as a command, skipping blank lines and lines starting with #");
+    println!("trace eval [expr]: Evaluates the expression like eval, printing every application performed along the way as a tree");
+    println!("set trace [on|off]: Turns tracing on or off for every let and eval");
+    println!("seed [u64]: Reseeds the random number generator used for simulation, so that what follows is reproducible");
+    println!("seed: Prints the seed which the random number generator was last seeded with");
+    println!("history [query]: Lists previously-entered commands, or only those containing the query if one is given");
     println!("help: Prints this help screen");
+    Result::Ok(())
 }
 
-pub fn handle_update_models(context_state : &mut ContextState) {
-    context_state.update_models();
-    println!("Models successfully updated");
+pub fn handle_load_bindings(path : String, bindings : &mut Bindings) -> Result<(), InspectorError> {
+    let path_contents = read_from_path(&path).map_err(|err| err.with_context("Load Bindings"))?;
+    let loaded_bindings = Bindings::deserialize(&path_contents).map_err(|err| err.with_context("Load Bindings"))?;
+    *bindings = loaded_bindings;
+    println!("Successfully loaded bindings");
+    Result::Ok(())
 }
 
-pub fn handle_save_models(path : String, context_state : &ContextState) {
-    let maybe_serialized_models = bincode::serialize(&context_state.interpreter_and_embedder_state);
-    match (maybe_serialized_models) {
-        Result::Ok(serialized_models) => {
-            let maybe_write_result = write_to_path(&path, &serialized_models); 
-            match (maybe_write_result) {
-                Result::Ok(_) => {
-                    println!("Successfully wrote out models");
-                },
-                Result::Err(err) => {
-                    println!("Failed to write out models: {}", err);
-                }
-            }
-        },
-        Result::Err(err) => {
-            println!("Serialization error: {}", err);
-        }
+pub fn handle_load_sobol_directions(path : String, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    let path_contents = read_from_path(&path).map_err(|err| err.with_context("Load Sobol Directions"))?;
+    let text = String::from_utf8_lossy(&path_contents);
+    let sobol_directions = SobolDirectionNumbers::parse(&text).map_err(|err| err.with_context("Load Sobol Directions"))?;
+    println!("Loaded Sobol direction numbers for {} dimensions", sobol_directions.get_num_dims());
+    glob_state.maybe_sobol_directions = Option::Some(Arc::new(sobol_directions));
+    Result::Ok(())
+}
+
+pub fn handle_save_bindings(path : String, bindings : &Bindings) -> Result<(), InspectorError> {
+    let serialized_bindings = bindings.serialize().map_err(|err| err.with_context("Save Bindings"))?;
+    write_to_path(&path, &serialized_bindings).map_err(|err| err.with_context("Save Bindings"))?;
+    println!("Successfully wrote out bindings");
+    Result::Ok(())
+}
+
+pub fn handle_seed(maybe_seed : Option<u64>, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    match (maybe_seed) {
+        Option::Some(seed) => glob_state.set_seed(seed),
+        Option::None => println!("{}", glob_state.seed)
     }
+    Result::Ok(())
 }
 
-pub fn handle_load_models(path : String, context_state : &mut ContextState, bindings : &mut Bindings) {
-    let maybe_path_contents = read_from_path(&path);
-    match (maybe_path_contents) {
-        Result::Ok(path_contents) => {
-            let maybe_models = bincode::deserialize::<SerializedInterpreterAndEmbedderState>(&path_contents);
-            match (maybe_models) {
-                Result::Ok(models) => {
-                    context_state.interpreter_and_embedder_state = models;
-                    bindings.clear();
-                    println!("Successfully loaded models");
-                },
-                Result::Err(err) => {
-                    println!("Deserialization error: {}", err);
-                }
-            }
-        },
-        Result::Err(err) => {
-            println!("Failed to read contents of path: {}", err);
-        }
+pub fn handle_history(query : String, history : &CommandHistory) -> Result<(), InspectorError> {
+    for (index, entry) in history.search(query.trim()) {
+        println!("{}: {}", index, entry);
     }
+    Result::Ok(())
+}
+
+pub fn handle_update_models(context_state : &mut ContextState) -> Result<(), InspectorError> {
+    context_state.update_models();
+    println!("Models successfully updated");
+    Result::Ok(())
+}
+
+pub fn handle_save_models(path : String, context_state : &mut ContextState) -> Result<(), InspectorError> {
+    let serialized_models = context_state.serialize_models().map_err(|err| err.with_context("Save Models"))?;
+    write_to_path(&path, &serialized_models).map_err(|err| err.with_context("Save Models"))?;
+    println!("Successfully wrote out models");
+    Result::Ok(())
+}
+
+pub fn handle_load_models(path : String, context_state : &mut ContextState, bindings : &mut Bindings) -> Result<(), InspectorError> {
+    let path_contents = read_from_path(&path).map_err(|err| err.with_context("Load Models"))?;
+    context_state.deserialize_models(&path_contents).map_err(|err| err.with_context("Load Models"))?;
+    bindings.clear();
+    println!("Successfully loaded models");
+    Result::Ok(())
 }
 
 
-pub fn handle_list_primitive_terms(type_text : String, context_state : &ContextState) {
+pub fn handle_list_primitive_terms(type_text : String, context_state : &ContextState) -> Result<(), InspectorError> {
     let maybe_type_number = type_text.trim().parse::<usize>();
     match (maybe_type_number) {
         Result::Err(err) => {
-            println!("List Primitive Terms: Unable to parse type number from {}", type_text);
-            println!("Due to error: {}", err);
+            let message = format!("Unable to parse type number from {}, due to error: {}", type_text, err);
+            Result::Err(InspectorError::Command(message).with_context("List Primitive Terms"))
         },
         Result::Ok(type_id) => {
-            let primitive_directory = &context_state.ctxt.primitive_directory;
-            let primitive_type_space = primitive_directory.primitive_type_spaces.get(&type_id).unwrap();
-            for i in 0..primitive_type_space.terms.len() {
-                let term_name = primitive_type_space.terms[i].get_name();
-                println!("p{}: {}", i, term_name);
+            check_type_exists(context_state.get_context(), type_id).map_err(|err| err.with_context("List Primitive Terms"))?;
+            let primitive_directory = &context_state.get_context().primitive_directory;
+            match (primitive_directory.primitive_type_spaces.get(&type_id)) {
+                Option::None => {
+                    println!("{} has no primitive terms", format_type(context_state.get_context(), type_id));
+                },
+                Option::Some(primitive_type_space) => {
+                    for i in 0..primitive_type_space.terms.len() {
+                        let term_name = primitive_type_space.terms[i].get_name();
+                        println!("p{}: {}", i, term_name);
+                    }
+                }
             }
+            Result::Ok(())
         }
     }
 }
 
-pub fn handle_list_types(context_state : &ContextState) {
-    let ctxt = &context_state.ctxt;
+pub fn handle_list_terms(type_text : String, context_state : &mut ContextState) -> Result<(), InspectorError> {
+    let type_id = type_text.trim().parse::<usize>()
+                  .map_err(|err| InspectorError::Command(format!("Unable to parse type number from {}, due to error: {}",
+                                                                 type_text, err)).with_context("List Terms"))?;
+    let rendered_terms = context_state.perform_on_models_ref(|interpreter_and_embedder_state|
+                                 render_term_list(&interpreter_and_embedder_state.interpreter_state,
+                                                  interpreter_and_embedder_state.get_context(), type_id))
+                         .map_err(|err| err.with_context("List Terms"))?;
+    println!("{}", rendered_terms);
+    Result::Ok(())
+}
+
+pub fn handle_show(term_text : String, context_state : &mut ContextState, bindings : &Bindings) -> Result<(), InspectorError> {
+    let rendered_summary = context_state.perform_on_models_ref(|interpreter_and_embedder_state| {
+        let term_ref = parse_term_ref(&term_text, bindings, interpreter_and_embedder_state)?;
+        Result::Ok(render_term_summary(&interpreter_and_embedder_state.interpreter_state,
+                                       interpreter_and_embedder_state.get_context(), &term_ref))
+    }).map_err(|err : InspectorError| err.with_context("Show"))?;
+    println!("{}", rendered_summary);
+    Result::Ok(())
+}
+
+pub fn handle_list_apps(query_text : String, context_state : &mut ContextState, bindings : &Bindings) -> Result<(), InspectorError> {
+    let rendered_app_results = context_state.perform_on_models_ref(|interpreter_and_embedder_state| {
+        let query = ApplicationQuery::parse(&query_text, bindings, interpreter_and_embedder_state)?;
+        Result::Ok(render_app_results(&interpreter_and_embedder_state.interpreter_state,
+                                      interpreter_and_embedder_state.get_context(), &query))
+    }).map_err(|err : InspectorError| err.with_context("List Apps"))?;
+    println!("{}", rendered_app_results);
+    Result::Ok(())
+}
+
+pub fn handle_neighbors(query_text : String, context_state : &mut ContextState, bindings : &Bindings) -> Result<(), InspectorError> {
+    let rendered_neighbors = context_state.perform_on_models_ref(|interpreter_and_embedder_state| {
+        let query = NeighborQuery::parse(&query_text, bindings, interpreter_and_embedder_state)?;
+        let neighbors = find_neighbors(&interpreter_and_embedder_state.embedder_state, &query)?;
+        Result::Ok(render_neighbors(&interpreter_and_embedder_state.interpreter_state, &query, &neighbors))
+    }).map_err(|err : InspectorError| err.with_context("Neighbors"))?;
+    println!("{}", rendered_neighbors);
+    Result::Ok(())
+}
+
+pub fn handle_list_types(context_state : &ContextState) -> Result<(), InspectorError> {
+    let ctxt = context_state.get_context();
     for type_id in 0..ctxt.get_total_num_types() {
         let kind = ctxt.get_type(type_id);
         let type_text = kind.display(ctxt);
         println!("#{}: {}", type_id, type_text);
     }
+    Result::Ok(())
 }
 
-pub fn handle_simulate(expr_text : String, context_state : &mut ContextState, bindings : &Bindings) {
-    let parse_result = parse_atom(&expr_text, bindings);
-    match (parse_result) {
-        Result::Err(err) => {
-            println!("Simulate: Expression Parsing Error: {}", err);
+pub fn handle_typeof(expr_text : String, context_state : &mut ContextState, bindings : &Bindings) -> Result<(), InspectorError> {
+    let (expr, spans) = parse_expression_with_spans(&expr_text, bindings, Option::Some(context_state.get_context()))
+                        .map_err(|err| err.with_context("Typeof"))?;
+    let type_id = context_state.check_parsed_expression(&expr, &expr_text, &spans)
+                               .map_err(|err| err.with_context("Typeof"))?;
+    println!("{}", format_type(context_state.get_context(), type_id));
+    Result::Ok(())
+}
+
+///Sobol sampling needs direction numbers for every dimension after the first, which don't come built-in
+fn check_sampling_strategy_available(options : &SimulationOptions,
+                                     maybe_sobol_directions : &Option<Arc<SobolDirectionNumbers>>) -> Result<(), InspectorError> {
+    if (options.strategy == SamplingStrategy::Sobol && !options.at_mean && maybe_sobol_directions.is_none()) {
+        return Result::Err(InspectorError::Command(format!("Sobol sampling needs Joe-Kuo direction numbers: download them from {}, and load them with load_sobol_directions [path]",
+                                                           JOE_KUO_URL)));
+    }
+    Result::Ok(())
+}
+
+pub fn make_sampler<'r, R : Rng + ?Sized>(options : &SimulationOptions, num_samples : usize,
+                                          maybe_sobol_directions : &Option<Arc<SobolDirectionNumbers>>,
+                                          rng : &'r mut R) -> Box<dyn TermSampler + 'r> {
+    if (options.at_mean) {
+        return Box::new(MeanSampler);
+    }
+    match (options.strategy, options.consistent) {
+        (SamplingStrategy::Plain, false) => Box::new(IndependentSampler::new(rng)),
+        (SamplingStrategy::Plain, true) => Box::new(ConsistentSampler::new(IndependentSampler::new(rng))),
+        (SamplingStrategy::Antithetic, false) => Box::new(AntitheticSampler::new(rng)),
+        (SamplingStrategy::Antithetic, true) => Box::new(ConsistentSampler::new(AntitheticSampler::new(rng))),
+        (SamplingStrategy::Halton, false) => {
+            Box::new(QuasiMonteCarloSampler::new(rng, HaltonSequence::new(), num_samples))
         },
-        Result::Ok((expr, _)) => {
-            let maybe_result_vec = context_state.simulate(expr);
-            match (maybe_result_vec) {
-                Result::Err(err) => {
-                    println!("Simulate: evaluation error: {}", err);
-                },
-                Result::Ok(result_vec) => {
-                    let result_string = format_typed_vector(&result_vec);
-                    println!("{}", &result_string);
-                }
-            }
+        (SamplingStrategy::Halton, true) => {
+            Box::new(ConsistentSampler::new(QuasiMonteCarloSampler::new(rng, HaltonSequence::new(), num_samples)))
+        },
+        (SamplingStrategy::Sobol, false) => {
+            Box::new(QuasiMonteCarloSampler::new(rng, SobolSequence::new(maybe_sobol_directions.clone()), num_samples))
+        },
+        (SamplingStrategy::Sobol, true) => {
+            Box::new(ConsistentSampler::new(QuasiMonteCarloSampler::new(rng, SobolSequence::new(maybe_sobol_directions.clone()),
+                                                                        num_samples)))
         }
     }
 }
 
-pub fn handle_evaluate(expr_text : String, context_state : &mut ContextState, bindings : &mut Bindings) {
-    handle_let("ans".to_owned(), expr_text, context_state, bindings);
-}
+pub fn handle_simulate<R : Rng + ?Sized>(options : SimulationOptions, expr_text : String,
+                                         context_state : &mut ContextState, bindings : &Bindings,
+                                         maybe_sobol_directions : &Option<Arc<SobolDirectionNumbers>>,
+                                         rng : &mut R) -> Result<(), InspectorError> {
+    check_sampling_strategy_available(&options, maybe_sobol_directions).map_err(|err| err.with_context("Simulate"))?;
+    let (expr, spans) = parse_expression_with_spans(&expr_text, bindings, Option::Some(context_state.get_context()))
+                        .map_err(|err| err.with_context("Simulate"))?;
+    context_state.check_parsed_expression(&expr, &expr_text, &spans)
+                 .map_err(|err| err.with_context("Simulate"))?;
+    let num_samples = options.maybe_num_samples.unwrap_or(1);
+    //Parallel batches each get their own generator, derived from one drawn from the session's
+    let seed = rng.gen::<u64>();
+    let sampled_vectors = context_state.simulate_samples(&expr, num_samples, seed,
+                                                         |batch_rng| make_sampler(&options, num_samples, maybe_sobol_directions, batch_rng))
+                                       .map_err(|err| err.with_context("Simulate"))?;
 
-pub fn handle_let(var_text : String, expr_text : String, 
-                  context_state : &mut ContextState, bindings : &mut Bindings) {
-    let parse_result = parse_atom(&expr_text, &*bindings);
-    match (parse_result) {
-        Result::Err(err) => {
-            println!("Let: Expression Parsing Error: {}", err);
+    match (options.maybe_num_samples) {
+        Option::None => {
+            let result_vec = TypedVector {
+                type_id : sampled_vectors.type_id,
+                vec : sampled_vectors.samples.row(0).to_owned()
+            };
+            println!("{}", format_typed_vector(&result_vec));
         },
-        Result::Ok((expr, _)) => {
-            let maybe_result_ref = context_state.eval(expr);            
-            match (maybe_result_ref) {
-                Result::Err(err) => {
-                    println!("Let: Expression Evaluation Error: {}", err);
-                },
-                Result::Ok(result_ref) => {
-                    let result_string = format_term_ref(&result_ref);
-                    bindings.write(var_text, result_ref);
-                    println!("{}", &result_string);
-                }
-            }
+        Option::Some(_) => {
+            let summary = SampleSummary::new(sampled_vectors.samples.view(), options.get_quantile_levels(),
+                                             sampled_vectors.block_size);
+            println!("{} samples of {} [{} sampling]", num_samples,
+                     format_type(context_state.get_context(), sampled_vectors.type_id), options.strategy.get_name());
+            println!("{}", summary.render());
         }
     }
+    if let Option::Some(output_path) = &options.maybe_output_path {
+        write_samples(output_path, sampled_vectors.samples.view()).map_err(|err| err.with_context("Simulate"))?;
+        println!("Wrote samples to {}", output_path);
+    }
+    Result::Ok(())
 }
 
-pub fn handle_save_context(path : String, context_state : &ContextState) {
-    let maybe_write_result = write_to_path(&path, &context_state.ctxt_bytes); 
-    match (maybe_write_result) {
-        Result::Ok(_) => {
-            println!("Successfully wrote out context");
-        },
-        Result::Err(err) => {
-            println!("Failed to write out context: {}", err);
+pub fn handle_compare<R : Rng + ?Sized>(options : SimulationOptions, expr_text : String,
+                                        context_state : &mut ContextState, bindings : &Bindings,
+                                        maybe_sobol_directions : &Option<Arc<SobolDirectionNumbers>>,
+                                        rng : &mut R) -> Result<(), InspectorError> {
+    check_sampling_strategy_available(&options, maybe_sobol_directions).map_err(|err| err.with_context("Compare"))?;
+    let (expr, spans) = parse_expression_with_spans(&expr_text, bindings, Option::Some(context_state.get_context()))
+                        .map_err(|err| err.with_context("Compare"))?;
+    let type_id = context_state.check_parsed_expression(&expr, &expr_text, &spans).map_err(|err| err.with_context("Compare"))?;
+    let ctxt = context_state.get_context();
+    if (!ctxt.is_vector_type(type_id)) {
+        return Result::Err(InspectorError::type_error(format!("Compare: {} has type {}, but only vector-typed results can be compared",
+                                                              expr_text, format_type(ctxt, type_id)))
+                           .at_span(&expr_text, spans.get_span()));
+    }
+
+    //Simulating first means that the simulations don't see any terms which the evaluation creates
+    let num_samples = options.maybe_num_samples.unwrap_or(DEFAULT_COMPARE_SAMPLES);
+    let seed = rng.gen::<u64>();
+    let sampled_vectors = context_state.simulate_samples(&expr, num_samples, seed,
+                                                         |batch_rng| make_sampler(&options, num_samples, maybe_sobol_directions, batch_rng))
+                                       .map_err(|err| err.with_context("Compare"))?;
+    let result_ref = context_state.eval_without_recording(expr).map_err(|err| err.with_context("Compare"))?;
+    let evaluated = match (&result_ref) {
+        TermReference::VecRef(_, noisy_vec) => from_noisy(noisy_vec.view()),
+        TermReference::FuncRef(_) => {
+            return Result::Err(InspectorError::evaluation(format!("Compare: {} evaluated to a function, {}",
+                                                                  expr_text, format_term_ref(&result_ref)))
+                               .at_span(&expr_text, spans.get_span()));
         }
+    };
+
+    let summary = SampleSummary::new(sampled_vectors.samples.view(), options.get_quantile_levels(),
+                                     sampled_vectors.block_size);
+    let report = DiscrepancyReport::new(evaluated.view(), summary);
+    println!("{} against {} samples of {} [{} sampling]", format_term_ref(&result_ref), num_samples,
+             format_type(context_state.get_context(), type_id), options.strategy.get_name());
+    println!("{}", report.render());
+    if let Option::Some(output_path) = &options.maybe_output_path {
+        write_samples(output_path, sampled_vectors.samples.view()).map_err(|err| err.with_context("Compare"))?;
+        println!("Wrote samples to {}", output_path);
     }
+    Result::Ok(())
+}
+
+pub fn handle_fit_report<R : Rng + ?Sized>(options : SimulationOptions, context_state : &mut ContextState,
+                                           maybe_sobol_directions : &Option<Arc<SobolDirectionNumbers>>,
+                                           rng : &mut R) -> Result<(), InspectorError> {
+    check_sampling_strategy_available(&options, maybe_sobol_directions).map_err(|err| err.with_context("Fit Report"))?;
+    let num_samples = options.maybe_num_samples.unwrap_or(DEFAULT_FIT_SAMPLES);
+    let seed = rng.gen::<u64>();
+    let rendered_fit_statistics = context_state.perform_on_models_ref(|interpreter_and_embedder_state| {
+        let all_fit_statistics = compute_fit_statistics(interpreter_and_embedder_state, num_samples, seed,
+                                                        |batch_rng| make_sampler(&options, num_samples, maybe_sobol_directions, batch_rng));
+        render_fit_statistics(interpreter_and_embedder_state.get_context(), &all_fit_statistics)
+    });
+    println!("Fit of {} samples per application [{} sampling]", num_samples, options.strategy.get_name());
+    println!("{}", rendered_fit_statistics);
+    Result::Ok(())
+}
+
+pub fn handle_evaluate(expr_text : String, trace_evaluation : bool,
+                       context_state : &mut ContextState, bindings : &mut Bindings) -> Result<(), InspectorError> {
+    handle_let("ans".to_owned(), expr_text, trace_evaluation, context_state, bindings)
 }
 
-pub fn write_to_path(path : &str, contents : &[u8]) -> Result<(), String> {
+pub fn handle_let(var_text : String, expr_text : String, trace_evaluation : bool,
+                  context_state : &mut ContextState, bindings : &mut Bindings) -> Result<(), InspectorError> {
+    let (expr, spans) = parse_expression_with_spans(&expr_text, &*bindings, Option::Some(context_state.get_context()))
+                        .map_err(|err| err.with_context("Let"))?;
+    context_state.check_parsed_expression(&expr, &expr_text, &spans)
+                 .map_err(|err| err.with_context("Let"))?;
+    let result_ref = if (trace_evaluation) {
+                         let (result_ref, maybe_trace) = context_state.trace_eval(expr).map_err(|err| err.with_context("Let"))?;
+                         if let Option::Some(trace) = maybe_trace {
+                             println!("{}", trace);
+                         }
+                         result_ref
+                     } else {
+                         context_state.eval(expr).map_err(|err| err.with_context("Let"))?
+                     };
+    let result_string = format_term_ref(&result_ref);
+    bindings.write(var_text, result_ref);
+    println!("{}", &result_string);
+    Result::Ok(())
+}
+
+pub fn handle_save_context(path : String, context_state : &ContextState) -> Result<(), InspectorError> {
+    write_to_path(&path, &context_state.ctxt_bytes).map_err(|err| err.with_context("Save Context"))?;
+    println!("Successfully wrote out context");
+    Result::Ok(())
+}
+
+pub fn write_to_path(path : &str, contents : &[u8]) -> Result<(), InspectorError> {
     let maybe_canonical_path = shellexpand::full(path);
     match (maybe_canonical_path) {
         Result::Ok(canonical_path) => {
-            let maybe_write_result = fs::write(&*canonical_path, contents);
-            match (maybe_write_result) {
-                Result::Ok(_) => Result::Ok(()),
-                Result::Err(err) => Result::Err(format!("Writing Error: {}", err))
-            }
+            fs::write(&*canonical_path, contents).map_err(|err| InspectorError::io(path, err))
         },
-        Result::Err(err) => Result::Err(format!("Path Resolution Error: {}", err))
+        Result::Err(err) => Result::Err(InspectorError::io(path, format!("Path Resolution Error: {}", err)))
     }
 }
 
-pub fn read_from_path(path : &str) -> Result<Vec<u8>, String> {
+pub fn read_from_path(path : &str) -> Result<Vec<u8>, InspectorError> {
     let maybe_canonical_path = shellexpand::full(path);
     match (maybe_canonical_path) {
         Result::Ok(canonical_path) => {
-            let maybe_path_contents = fs::read(&*canonical_path);
-            match (maybe_path_contents) {
-                Result::Ok(path_contents) => Result::Ok(path_contents),
-                Result::Err(err) => Result::Err(format!("Read Error: {}", err))
-            }
+            fs::read(&*canonical_path).map_err(|err| InspectorError::io(path, err))
         },
-        Result::Err(err) => Result::Err(format!("Path Resolution Error: {}", err))
+        Result::Err(err) => Result::Err(InspectorError::io(path, format!("Path Resolution Error: {}", err)))
     }
 }
 
-pub fn handle_load_context(path : String, glob_state : &mut GlobalState) {
-    let maybe_path_contents = read_from_path(&path);
-    match (maybe_path_contents) {
-        Result::Err(err) => {
-            println!("Load Context: IO Error: {}", err);
-        },
-        Result::Ok(path_contents) => {
-            let maybe_context = glob_state.lib_handle.deserialize_serialized_context(&path_contents);
-            match (maybe_context) {
-                Result::Err(err) => {
-                    println!("Load Context: JSON Error: {}", err);
-                },
-                Result::Ok(context) => {
-                    glob_state.set_context(path_contents, context);
-                }
-            }
-        }
-    }
+pub fn handle_load_context(path : String, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    let path_contents = read_from_path(&path).map_err(|err| err.with_context("Load Context"))?;
+    let context = glob_state.lib_handle.deserialize_serialized_context(&path_contents)
+                            .map_err(|err| err.with_context("Load Context"))?;
+    glob_state.set_context(path_contents, context);
+    Result::Ok(())
 }
 
-pub fn handle_generate_context(path : String, glob_state : &mut GlobalState) {
-    let maybe_path_contents = read_from_path(&path);
-    match (maybe_path_contents) {
-        Result::Err(err) => {
-            println!("Generate Context: IO Error: {}", err);
-        },
-        Result::Ok(path_contents) => {
-            let maybe_context_json = glob_state.lib_handle.generate_serialized_context(&path_contents);
-            match (maybe_context_json) {
-                Result::Err(err) => {
-                    println!("Generate Context (Generate): JSON Error: {}", err);
-                },
-                Result::Ok(context_json) => {
-                    let maybe_context = glob_state.lib_handle.deserialize_serialized_context(&context_json);
-                    match (maybe_context) {
-                        Result::Err(err) => {
-                            println!("Generate Context (Deserialize): JSON Error: {}", err);
-                        },
-                        Result::Ok(context) => {
-                            glob_state.set_context(context_json, context);
-                        }
-                    }
-                }
-            }
-        }
-    }
+pub fn handle_generate_context(path : String, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    let path_contents = read_from_path(&path).map_err(|err| err.with_context("Generate Context"))?;
+    let context_json = glob_state.lib_handle.generate_serialized_context(&path_contents)
+                                 .map_err(|err| err.with_context("Generate Context"))?;
+    let context = glob_state.lib_handle.deserialize_serialized_context(&context_json)
+                            .map_err(|err| err.with_context("Generate Context"))?;
+    glob_state.set_context(context_json, context);
+    Result::Ok(())
 }
 
-pub fn handle_parse(line : String, bindings : &Bindings) {
-    let parse_result = parse_s_expression(line.as_str(), &bindings);
-    match (parse_result) {
-        Result::Ok((expr, _)) => {
-            println!("{}", expr);
-        },
-        Result::Err(err) => {
-            println!("Parsing error: {}", err);
-        }
-    }
+pub fn handle_parse(line : String, glob_state : &GlobalState) -> Result<(), InspectorError> {
+    let maybe_ctxt = glob_state.maybe_context_state.as_ref().map(|context_state| context_state.get_context());
+    let expr = parse_expression(line.as_str(), &glob_state.bindings, maybe_ctxt)
+               .map_err(|err| err.with_context("Parse"))?;
+    println!("{}", expr);
+    Result::Ok(())
 }
@@ -0,0 +1,91 @@
+use std::fmt::Write as FmtWrite;
+use ndarray::*;
+use crate::statistics::*;
+
+pub const DEFAULT_COMPARE_SAMPLES : usize = 1000;
+
+///How far simulations of an expression are from the vector it actually evaluates to
+pub struct DiscrepancyReport {
+    pub evaluated : Array1<f64>,
+    pub summary : SampleSummary,
+    ///Squared Euclidean distance between the simulated mean and the evaluated vector
+    pub squared_error : f64,
+    ///Per-dimension distance of the evaluated vector from the simulated mean, in standard deviations
+    ///[None where the simulations don't vary, so that there's no scale to measure it in]
+    pub z_scores : Vec<Option<f64>>,
+    ///The levels of the quantiles bounding the predictive interval for each dimension
+    pub interval_levels : (f64, f64),
+    ///Per-dimension, whether the evaluated vector falls inside the predictive interval
+    pub inside_interval : Vec<bool>
+}
+
+impl DiscrepancyReport {
+    ///Compares the evaluated vector against the summary of its simulations, using the
+    ///lowest and highest quantiles of the summary as the predictive interval
+    pub fn new(evaluated : ArrayView1<f32>, summary : SampleSummary) -> DiscrepancyReport {
+        let evaluated = evaluated.mapv(|value| value as f64);
+        let errors = &summary.mean - &evaluated;
+        let squared_error = errors.dot(&errors);
+        let z_scores = (0..evaluated.len()).map(|dim| {
+                           let std_dev = summary.std_dev[dim];
+                           if (std_dev > 0.0) {
+                               Option::Some((evaluated[dim] - summary.mean[dim]) / std_dev)
+                           } else {
+                               Option::None
+                           }
+                       }).collect();
+
+        let levels = &summary.quantile_levels;
+        let lower_index = (0..levels.len()).fold(0, |best, i| if (levels[i] < levels[best]) { i } else { best });
+        let upper_index = (0..levels.len()).fold(0, |best, i| if (levels[i] > levels[best]) { i } else { best });
+        let inside_interval = (0..evaluated.len()).map(|dim| {
+                                  let value = evaluated[dim];
+                                  summary.quantiles[[lower_index, dim]] <= value && value <= summary.quantiles[[upper_index, dim]]
+                              }).collect();
+
+        DiscrepancyReport {
+            evaluated,
+            interval_levels : (levels[lower_index], levels[upper_index]),
+            summary,
+            squared_error,
+            z_scores,
+            inside_interval
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let (lower_level, upper_level) = self.interval_levels;
+        let num_inside = self.inside_interval.iter().filter(|inside| **inside).count();
+        let mut result = String::new();
+        let _ = writeln!(result, "Squared error of the simulated mean: {:.6}", self.squared_error);
+        let _ = writeln!(result, "{} of {} dimensions inside the [q{}, q{}] predictive interval",
+                       num_inside, self.inside_interval.len(), lower_level, upper_level);
+        let _ = write!(result, "{:>5} {:>12} {:>12} {:>12} {:>8} {:>7}", "dim", "evaluated", "mean", "std", "z", "inside");
+        for dim in 0..self.evaluated.len() {
+            let z_score_text = match (self.z_scores[dim]) {
+                Option::Some(z_score) => format!("{:.3}", z_score),
+                Option::None => "n/a".to_owned()
+            };
+            let _ = write!(result, "\n{:>5} {:>12.6} {:>12.6} {:>12.6} {:>8} {:>7}", dim, self.evaluated[dim],
+                           self.summary.mean[dim], self.summary.std_dev[dim], z_score_text,
+                           if (self.inside_interval[dim]) { "yes" } else { "no" });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_simulations_have_no_z_score() {
+        let samples = array![[1.0f32, 2.0], [1.0, 4.0], [1.0, 6.0]];
+        let summary = SampleSummary::new(samples.view(), &[0.0, 1.0], 1);
+        let report = DiscrepancyReport::new(array![3.0f32, 4.0].view(), summary);
+        assert_eq!(report.z_scores, vec![Option::None, Option::Some(0.0)]);
+        assert_eq!(report.inside_interval, vec![false, true]);
+        assert!(report.render().contains("n/a"));
+        assert!(!report.render().contains("inf"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::fmt;
+use std::any::Any;
+use crate::parsers::*;
+use crate::tokenizer::*;
+
+///Everything which can go wrong while running an inspector command
+#[derive(Clone, Debug)]
+pub enum InspectorError {
+    ///An unrecognized command, or a command with malformed arguments
+    Command(String),
+    ///A malformed expression, together with the text it was parsed from
+    Parse {
+        source : String,
+        error : ParseError
+    },
+    ///A reference to an identifier or primitive name which doesn't exist
+    Resolution {
+        message : String,
+        maybe_source_span : Option<SourceSpan>
+    },
+    ///An ill-typed expression
+    Type {
+        message : String,
+        maybe_source_span : Option<SourceSpan>
+    },
+    Evaluation {
+        message : String,
+        maybe_source_span : Option<SourceSpan>
+    },
+    Simulation(String),
+    Io {
+        path : String,
+        message : String
+    },
+    Serialization(String),
+    ///A failure to load the context generator library, or to locate its symbols
+    LibraryLoading(String),
+    ///A failure reported by the context generator library while generating a context
+    ContextGeneration(String),
+    ///A contextual command was issued without a loaded context
+    NoContext,
+    ///A panic caught while running a command, with the panic message and where it happened, if known
+    Panic {
+        message : String,
+        maybe_location : Option<String>,
+        ///Whether the models had to be reset, since the panic may have left them half-updated
+        models_reset : bool
+    },
+    ///A failing command within a script
+    Script {
+        origin : String,
+        line : usize,
+        inner : Box<InspectorError>
+    },
+    ///Any other error, annotated with what was being done when it happened
+    WithContext {
+        context : String,
+        inner : Box<InspectorError>
+    }
+}
+
+///The text that an error was found in, and the part of it which the error refers to
+#[derive(Clone, Debug)]
+pub struct SourceSpan {
+    pub source : String,
+    pub span : Span
+}
+
+fn render_with_source_span(message : &str, maybe_source_span : &Option<SourceSpan>) -> String {
+    match (maybe_source_span) {
+        Option::Some(source_span) => render_span(message, &source_span.source, source_span.span),
+        Option::None => message.to_owned()
+    }
+}
+
+impl InspectorError {
+    pub fn resolution(message : String) -> InspectorError {
+        InspectorError::Resolution {
+            message,
+            maybe_source_span : Option::None
+        }
+    }
+
+    pub fn type_error(message : String) -> InspectorError {
+        InspectorError::Type {
+            message,
+            maybe_source_span : Option::None
+        }
+    }
+
+    pub fn evaluation(message : String) -> InspectorError {
+        InspectorError::Evaluation {
+            message,
+            maybe_source_span : Option::None
+        }
+    }
+
+    ///Points a resolution, type or evaluation error at the given part of the text it came from,
+    ///unless it's already been pointed somewhere more specific. Other errors are left alone.
+    pub fn at_span(self, source : &str, span : Span) -> InspectorError {
+        match (self) {
+            InspectorError::Resolution { message, maybe_source_span : Option::None } => InspectorError::Resolution {
+                message,
+                maybe_source_span : Option::Some(SourceSpan { source : source.to_owned(), span })
+            },
+            InspectorError::Type { message, maybe_source_span : Option::None } => InspectorError::Type {
+                message,
+                maybe_source_span : Option::Some(SourceSpan { source : source.to_owned(), span })
+            },
+            InspectorError::Evaluation { message, maybe_source_span : Option::None } => InspectorError::Evaluation {
+                message,
+                maybe_source_span : Option::Some(SourceSpan { source : source.to_owned(), span })
+            },
+            InspectorError::WithContext { context, inner } => InspectorError::WithContext {
+                context,
+                inner : Box::new(inner.at_span(source, span))
+            },
+            _ => self
+        }
+    }
+
+    pub fn io<E : fmt::Display>(path : &str, err : E) -> InspectorError {
+        InspectorError::Io {
+            path : path.to_owned(),
+            message : err.to_string()
+        }
+    }
+
+    pub fn parse(source : &str, error : ParseError) -> InspectorError {
+        InspectorError::Parse {
+            source : source.to_owned(),
+            error
+        }
+    }
+
+    ///Describes the payload of a caught panic, which is usually a message string,
+    ///together with the source location that the panic hook recorded for it
+    pub fn from_panic(panic_payload : Box<dyn Any + Send>, maybe_location : Option<String>,
+                      models_reset : bool) -> InspectorError {
+        let message = if let Option::Some(message) = panic_payload.downcast_ref::<&str>() {
+                          (*message).to_owned()
+                      } else if let Option::Some(message) = panic_payload.downcast_ref::<String>() {
+                          message.clone()
+                      } else {
+                          "<non-string panic payload>".to_owned()
+                      };
+        InspectorError::Panic {
+            message,
+            maybe_location,
+            models_reset
+        }
+    }
+
+    pub fn with_context(self, context : &str) -> InspectorError {
+        InspectorError::WithContext {
+            context : context.to_owned(),
+            inner : Box::new(self)
+        }
+    }
+
+    ///The underlying error, with any script locations and context annotations stripped away
+    pub fn root(&self) -> &InspectorError {
+        match (self) {
+            InspectorError::Script { inner, .. } => inner.root(),
+            InspectorError::WithContext { inner, .. } => inner.root(),
+            _ => self
+        }
+    }
+}
+
+impl fmt::Display for InspectorError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self) {
+            InspectorError::Command(message) => write!(f, "Command parsing error: {}", message),
+            InspectorError::Parse { source, error } => write!(f, "Expression Parsing Error: {}", error.render(source)),
+            InspectorError::Resolution { message, maybe_source_span } => {
+                write!(f, "{}", render_with_source_span(message, maybe_source_span))
+            },
+            InspectorError::Type { message, maybe_source_span } => {
+                write!(f, "Type Error: {}", render_with_source_span(message, maybe_source_span))
+            },
+            InspectorError::Evaluation { message, maybe_source_span } => {
+                write!(f, "Evaluation Error: {}", render_with_source_span(message, maybe_source_span))
+            },
+            InspectorError::Simulation(message) => write!(f, "Simulation Error: {}", message),
+            InspectorError::Io { path, message } => write!(f, "IO Error for {}: {}", path, message),
+            InspectorError::Serialization(message) => write!(f, "Serialization Error: {}", message),
+            InspectorError::LibraryLoading(message) => write!(f, "Library Loading Error: {}", message),
+            InspectorError::ContextGeneration(message) => write!(f, "Context Generation Error: {}", message),
+            InspectorError::NoContext => {
+                write!(f, "This command may not be executed without a currently-loaded context")
+            },
+            InspectorError::Panic { message, maybe_location, models_reset } => {
+                match (maybe_location) {
+                    Option::Some(location) => write!(f, "Command panicked at {}: {}", location, message)?,
+                    Option::None => write!(f, "Command panicked: {}", message)?
+                }
+                if (*models_reset) {
+                    write!(f, "\nThe models have been reset, so any bindings to non-primitive terms are now stale")?;
+                }
+                Result::Ok(())
+            },
+            InspectorError::Script { origin, line, inner } => write!(f, "{}:{}: {}", origin, line, inner),
+            InspectorError::WithContext { context, inner } => write!(f, "{}: {}", context, inner)
+        }
+    }
+}
+
+impl std::error::Error for InspectorError {
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_span_puts_carets_under_the_span() {
+        let err = InspectorError::type_error("Bad argument".to_owned()).at_span("(f #3p0 x)", Span::new(3, 7));
+        assert_eq!(err.to_string(), "Type Error: Bad argument\n    (f #3p0 x)\n       ^^^^");
+    }
+
+    #[test]
+    fn at_span_counts_columns_in_characters() {
+        //ƒ and é are two bytes each in UTF-8, but one column each
+        let source = "(ƒ é #3p0)";
+        let start = source.find('#').unwrap();
+        let err = InspectorError::resolution("Unknown".to_owned()).at_span(source, Span::new(start, start + 4));
+        assert_eq!(err.to_string(), "Unknown\n    (ƒ é #3p0)\n         ^^^^");
+
+        let err = InspectorError::evaluation("Failed".to_owned()).at_span(source, Span::new(1, 3));
+        assert_eq!(err.to_string(), "Evaluation Error: Failed\n    (ƒ é #3p0)\n     ^");
+    }
+
+    #[test]
+    fn at_span_keeps_the_most_specific_location() {
+        let err = InspectorError::type_error("Bad argument".to_owned())
+                  .at_span("(f x)", Span::new(3, 4))
+                  .with_context("Compare")
+                  .at_span("(f x)", Span::new(0, 5));
+        assert_eq!(err.to_string(), "Compare: Type Error: Bad argument\n    (f x)\n       ^");
+        //Errors without a location to fill in are left alone
+        let err = InspectorError::Simulation("No embedding".to_owned()).at_span("(f x)", Span::new(0, 5));
+        assert_eq!(err.to_string(), "Simulation Error: No embedding");
+    }
+
+    #[test]
+    fn contexts_nest_and_root_unwraps_them() {
+        let err = InspectorError::NoContext.with_context("Evaluate").with_context("Source");
+        assert_eq!(err.to_string(),
+                   "Source: Evaluate: This command may not be executed without a currently-loaded context");
+        assert!(matches!(err.root(), InspectorError::NoContext));
+
+        let err = InspectorError::Script {
+            origin : "run.fi".to_owned(),
+            line : 3,
+            inner : Box::new(InspectorError::Command("Unknown command".to_owned()).with_context("Parse"))
+        };
+        assert_eq!(err.to_string(), "run.fi:3: Parse: Command parsing error: Unknown command");
+        assert!(matches!(err.root(), InspectorError::Command(message) if message == "Unknown command"));
+    }
+
+    #[test]
+    fn panics_are_described_by_their_payloads() {
+        let err = InspectorError::from_panic(Box::new("index out of bounds"), Option::Some("src/simulate.rs:10:5".to_owned()), false);
+        assert_eq!(err.to_string(), "Command panicked at src/simulate.rs:10:5: index out of bounds");
+
+        let err = InspectorError::from_panic(Box::new(format!("{} is not a vector", 3)), Option::None, false);
+        assert_eq!(err.to_string(), "Command panicked: 3 is not a vector");
+
+        let err = InspectorError::from_panic(Box::new(42), Option::None, false);
+        assert_eq!(err.to_string(), "Command panicked: <non-string panic payload>");
+    }
+
+    #[test]
+    fn panics_report_model_resets() {
+        let err = InspectorError::from_panic(Box::new("Singular matrix"), Option::None, true);
+        assert_eq!(err.to_string(), "Command panicked: Singular matrix\n\
+                                     The models have been reset, so any bindings to non-primitive terms are now stale");
+    }
+}
@@ -1,6 +1,10 @@
 use fetish_lib::everything::*;
 use noisy_float::*;
 use std::fmt;
+use crate::error::*;
+use crate::parsers::*;
+use crate::tokenizer::*;
+use crate::trace::*;
 
 #[derive(Clone)]
 pub enum FuncExpression {
@@ -113,70 +117,146 @@ impl DisplayableWithState for FuncExpression {
     }
 }
 
-pub fn build_application(mut expr_vec : Vec<Expression>) -> Result<AppExpression, String> {
+///Where an expression and each of its subexpressions came from in the text it was parsed from,
+///mirroring the shape of the expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExpressionSpans {
+    Ref(Span),
+    App {
+        span : Span,
+        func_spans : Box<ExpressionSpans>,
+        arg_spans : Box<ExpressionSpans>
+    }
+}
+
+impl ExpressionSpans {
+    pub fn get_span(&self) -> Span {
+        match (self) {
+            ExpressionSpans::Ref(span) => *span,
+            ExpressionSpans::App { span, .. } => *span
+        }
+    }
+}
+
+///Builds the curried application of the first expression to the rest, where the span
+///is that of the s-expression which the expressions came from. The inner applications
+///of the curried result span from the start of the s-expression to the end of their argument.
+pub fn build_application(mut expr_vec : Vec<(Expression, ExpressionSpans)>,
+                         span : Span) -> Result<(AppExpression, ExpressionSpans), ParseError> {
     if (expr_vec.is_empty()) {
-        return Result::Err("Empty expression".to_string());
+        return Result::Err(ParseError::new("Empty expression".to_string(), span));
     }
     if (expr_vec.len() < 2) {
-        return Result::Err("Singleton expressions are disallowed".to_string());
+        return Result::Err(ParseError::new("Singleton expressions are disallowed".to_string(), span));
     }
-    let func_expr = expr_vec.remove(0);
+    let (func_expr, func_spans) = expr_vec.remove(0);
     let maybe_func_expr = AppExpression::maybe_from_expression(func_expr);
     match (maybe_func_expr) {
-        Option::None => Result::Err("Cannot apply vector as a function".to_string()),
+        Option::None => Result::Err(ParseError::new("Cannot apply vector as a function".to_string(), span)),
         Option::Some(func_expr) => {
-            let arg_expr = expr_vec.remove(0);
-            let mut result = AppExpression::new(func_expr, arg_expr);
-            for arg_expr in expr_vec.drain(..) {
-                let func_expr = FuncExpression::App(result);
-                result = AppExpression::new(func_expr, arg_expr);
+            let num_args = expr_vec.len();
+            let mut result = func_expr;
+            let mut result_spans = func_spans;
+            for (i, (arg_expr, arg_spans)) in expr_vec.drain(..).enumerate() {
+                let app_span = if (i + 1 == num_args) {
+                                   span
+                               } else {
+                                   Span::new(span.start, arg_spans.get_span().end)
+                               };
+                result = FuncExpression::App(AppExpression::new(result, arg_expr));
+                result_spans = ExpressionSpans::App {
+                    span : app_span,
+                    func_spans : Box::new(result_spans),
+                    arg_spans : Box::new(arg_spans)
+                };
+            }
+            match (result) {
+                FuncExpression::App(app_expr) => Result::Ok((app_expr, result_spans)),
+                FuncExpression::Func(_) => unreachable!()
             }
-            Result::Ok(result)
         }
     }
 }
 
+//Evaluates expressions, performing [and recording] every application along the way.
+//The _with_trace methods also fill in the given trace sink, if there is one, with the trace of the
+//evaluated expression's applications, which is left as None if it involved no applications.
 pub trait EvaluatesExpressions {
-    fn evaluate_app_expression(&mut self, app_expr : AppExpression) -> Result<TermReference, String>;
-    fn evaluate_func_expression(&mut self, func_expr : FuncExpression) -> Result<TermPointer, String>;
-    fn evaluate_expression(&mut self, expr : Expression) -> Result<TermReference, String>;
+    fn evaluate_app_expression_with_trace(&mut self, app_expr : AppExpression,
+                                          maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermReference, InspectorError>;
+    fn evaluate_func_expression_with_trace(&mut self, func_expr : FuncExpression,
+                                           maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermPointer, InspectorError>;
+    fn evaluate_expression_with_trace(&mut self, expr : Expression,
+                                      maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermReference, InspectorError>;
+
+    fn evaluate_app_expression(&mut self, app_expr : AppExpression) -> Result<TermReference, InspectorError> {
+        self.evaluate_app_expression_with_trace(app_expr, Option::None)
+    }
+    fn evaluate_func_expression(&mut self, func_expr : FuncExpression) -> Result<TermPointer, InspectorError> {
+        self.evaluate_func_expression_with_trace(func_expr, Option::None)
+    }
+    fn evaluate_expression(&mut self, expr : Expression) -> Result<TermReference, InspectorError> {
+        self.evaluate_expression_with_trace(expr, Option::None)
+    }
 }
 
 impl <'a> EvaluatesExpressions for InterpreterAndEmbedderState<'a> {
-    fn evaluate_app_expression(&mut self, app_expr : AppExpression) -> Result<TermReference, String> {
+    fn evaluate_app_expression_with_trace(&mut self, app_expr : AppExpression,
+                                          maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermReference, InspectorError> {
         let func_expr = *app_expr.func_expr;
         let arg_expr = *app_expr.arg_expr;
-        
-        let func_ptr = self.evaluate_func_expression(func_expr)?;
-        let arg_ref = self.evaluate_expression(arg_expr)?;
+
+        let is_tracing = maybe_trace_sink.is_some();
+        let mut maybe_func_trace = Option::None;
+        let mut maybe_arg_trace = Option::None;
+        let func_ptr = self.evaluate_func_expression_with_trace(func_expr, if (is_tracing) {Option::Some(&mut maybe_func_trace)} else {Option::None})?;
+        let arg_ref = self.evaluate_expression_with_trace(arg_expr, if (is_tracing) {Option::Some(&mut maybe_arg_trace)} else {Option::None})?;
         let term_app = TermApplication {
             func_ptr,
             arg_ref
         };
-        
-        let result_ref = self.evaluate(&term_app);
-        Result::Ok(result_ref)
+
+        match (maybe_trace_sink) {
+            Option::None => Result::Ok(self.evaluate(&term_app)),
+            Option::Some(trace_sink) => {
+                let already_computed = self.interpreter_state.application_tables.get(&func_ptr.type_id)
+                                           .map(|application_table| application_table.has_computed(&term_app))
+                                           .unwrap_or(false);
+                let result_ref = self.evaluate(&term_app);
+                *trace_sink = Option::Some(EvaluationTrace {
+                    term_app,
+                    result_ref : result_ref.clone(),
+                    result_type_id : get_term_ref_type_id(&result_ref),
+                    newly_created : !already_computed,
+                    maybe_func_trace : maybe_func_trace.map(Box::new),
+                    maybe_arg_trace : maybe_arg_trace.map(Box::new)
+                });
+                Result::Ok(result_ref)
+            }
+        }
     }
-    fn evaluate_func_expression(&mut self, func_expr : FuncExpression) -> Result<TermPointer, String> {
+    fn evaluate_func_expression_with_trace(&mut self, func_expr : FuncExpression,
+                                           maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermPointer, InspectorError> {
         match (func_expr) {
             FuncExpression::Func(term_ptr) => Result::Ok(term_ptr),
             FuncExpression::App(app_expr) => {
                 let formatted_app = format!("{}", &app_expr);
-                let result_ref = self.evaluate_app_expression(app_expr)?;
+                let result_ref = self.evaluate_app_expression_with_trace(app_expr, maybe_trace_sink)?;
                 match (result_ref) {
                     TermReference::VecRef(_, _) => {
-                        Result::Err(format!("Expected function, but obtained vector from evaluating {}", 
-                                            formatted_app))
+                        Result::Err(InspectorError::evaluation(format!("Expected function, but obtained vector from evaluating {}", 
+                                                                       formatted_app)))
                     },
                     TermReference::FuncRef(func_ptr) => Result::Ok(func_ptr)
                 }
             }
         }
     }
-    fn evaluate_expression(&mut self, expr : Expression) -> Result<TermReference, String> {
+    fn evaluate_expression_with_trace(&mut self, expr : Expression,
+                                      maybe_trace_sink : Option<&mut Option<EvaluationTrace>>) -> Result<TermReference, InspectorError> {
         match (expr) {
             Expression::Ref(term_ref) => Result::Ok(term_ref),
-            Expression::App(app_expression) => self.evaluate_app_expression(app_expression)
+            Expression::App(app_expression) => self.evaluate_app_expression_with_trace(app_expression, maybe_trace_sink)
         }
     }
 }
@@ -0,0 +1,203 @@
+use std::fmt::Write as FmtWrite;
+use std::f64::consts::PI;
+use fetish_lib::everything::*;
+use ndarray::*;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::expression::*;
+use crate::simulate::*;
+use crate::statistics::*;
+use crate::applications::*;
+use crate::typecheck::*;
+
+pub const DEFAULT_FIT_SAMPLES : usize = 100;
+
+///Nominal coverages of the central predictive intervals which calibration is checked against
+pub const FIT_COVERAGE_LEVELS : &[f64] = &[0.5, 0.9];
+
+///Predictive variances are floored at this, so that a collapsed model doesn't give infinite densities
+pub const MIN_PREDICTIVE_VARIANCE : f64 = 1e-6;
+
+///How well simulations from the current embeddings reproduce the recorded applications
+///of functions of a single type
+pub struct FitStatistics {
+    pub func_type_id : TypeId,
+    pub num_apps : usize,
+    ///Applications which couldn't be simulated [for instance, for lack of an embedding]
+    pub num_skipped : usize,
+    pub num_dims : usize,
+    pub sum_squared_error : f64,
+    ///Log predictive densities are summed over dimensions within an application
+    pub sum_log_density : f64,
+    ///Indexed like FIT_COVERAGE_LEVELS, counting dimensions of all applications
+    pub num_inside : Vec<usize>
+}
+
+///The quantile levels bounding each of the central intervals in FIT_COVERAGE_LEVELS
+fn get_interval_quantile_levels() -> Vec<f64> {
+    FIT_COVERAGE_LEVELS.iter()
+                       .flat_map(|coverage| vec![(1.0 - coverage) / 2.0, (1.0 + coverage) / 2.0])
+                       .collect()
+}
+
+impl FitStatistics {
+    pub fn new(func_type_id : TypeId) -> FitStatistics {
+        FitStatistics {
+            func_type_id,
+            num_apps : 0,
+            num_skipped : 0,
+            num_dims : 0,
+            sum_squared_error : 0.0,
+            sum_log_density : 0.0,
+            num_inside : vec![0; FIT_COVERAGE_LEVELS.len()]
+        }
+    }
+
+    ///Adds an application which evaluated to the given vector, and simulated to the given samples.
+    ///The log density is that of a Gaussian with the samples' mean and variance [floored at
+    ///MIN_PREDICTIVE_VARIANCE], whereas coverage is checked against the samples' empirical quantiles.
+    pub fn add(&mut self, evaluated : ArrayView1<f32>, sampled_vectors : &SampledVectors) {
+        let summary = SampleSummary::new(sampled_vectors.samples.view(), &get_interval_quantile_levels(),
+                                         sampled_vectors.block_size);
+        for dim in 0..evaluated.len() {
+            let value = evaluated[dim] as f64;
+            let error = value - summary.mean[dim];
+            let variance = summary.std_dev[dim].powi(2).max(MIN_PREDICTIVE_VARIANCE);
+            self.sum_squared_error += error * error;
+            self.sum_log_density -= 0.5 * ((2.0 * PI * variance).ln() + error * error / variance);
+            for i in 0..FIT_COVERAGE_LEVELS.len() {
+                let lower = summary.quantiles[[2 * i, dim]];
+                let upper = summary.quantiles[[2 * i + 1, dim]];
+                if (lower <= value && value <= upper) {
+                    self.num_inside[i] += 1;
+                }
+            }
+        }
+        self.num_dims += evaluated.len();
+        self.num_apps += 1;
+    }
+
+    ///The averages below are None when every application was skipped, leaving nothing to average over
+    pub fn get_rmse(&self) -> Option<f64> {
+        if (self.num_apps == 0) {
+            return Option::None;
+        }
+        Option::Some((self.sum_squared_error / (self.num_dims as f64)).sqrt())
+    }
+
+    pub fn get_mean_log_density(&self) -> Option<f64> {
+        if (self.num_apps == 0) {
+            return Option::None;
+        }
+        Option::Some(self.sum_log_density / (self.num_apps as f64))
+    }
+
+    pub fn get_coverage(&self, level_index : usize) -> Option<f64> {
+        if (self.num_apps == 0) {
+            return Option::None;
+        }
+        Option::Some((self.num_inside[level_index] as f64) / (self.num_dims as f64))
+    }
+}
+
+///Simulates every recorded application whose result is a vector, num_samples times each,
+///returning statistics for each function type which has any such applications
+pub fn compute_fit_statistics<'a, F>(zelf : &InterpreterAndEmbedderState<'a>, num_samples : usize, seed : u64,
+                                     make_sampler : F) -> Vec<FitStatistics>
+    where F : for<'r> Fn(&'r mut StdRng) -> Box<dyn TermSampler + 'r> + Sync {
+    let ctxt = zelf.get_context();
+    //Each application gets its own seed, drawn in a fixed order
+    let mut seed_rng = StdRng::seed_from_u64(seed);
+    let mut func_type_ids : Vec<TypeId> = zelf.interpreter_state.application_tables.keys().cloned().collect();
+    func_type_ids.sort();
+
+    let mut result = Vec::new();
+    for func_type_id in func_type_ids {
+        match (ctxt.get_type(func_type_id)) {
+            Type::FuncType(_, ret_type_id) if ctxt.is_vector_type(ret_type_id) => {},
+            _ => continue
+        }
+        let query = ApplicationQuery::all(func_type_id);
+        let mut fit_statistics = FitStatistics::new(func_type_id);
+        for app_result in find_app_results(&zelf.interpreter_state, ctxt, &query) {
+            let evaluated = match (app_result.get_ret_ref()) {
+                TermReference::VecRef(_, noisy_vec) => from_noisy(noisy_vec.view()),
+                TermReference::FuncRef(_) => continue
+            };
+            let app_expr = Expression::App(AppExpression::new(FuncExpression::Func(app_result.get_func_ptr()),
+                                                              Expression::Ref(app_result.get_arg_ref())));
+            let app_seed = seed_rng.gen::<u64>();
+            match (simulate_samples_in_parallel(zelf, &app_expr, num_samples, app_seed, &make_sampler)) {
+                Result::Ok(sampled_vectors) => fit_statistics.add(evaluated.view(), &sampled_vectors),
+                Result::Err(err) => {
+                    debug!("Skipping {} in fit report: {}", format_app_result(&zelf.interpreter_state, &app_result), err);
+                    fit_statistics.num_skipped += 1;
+                }
+            }
+        }
+        if (fit_statistics.num_apps + fit_statistics.num_skipped > 0) {
+            result.push(fit_statistics);
+        }
+    }
+    result
+}
+
+fn format_maybe_statistic(maybe_statistic : Option<f64>, precision : usize) -> String {
+    match (maybe_statistic) {
+        Option::Some(statistic) => format!("{:.*}", precision, statistic),
+        Option::None => "-".to_owned()
+    }
+}
+
+///Renders a table with one row per function type
+pub fn render_fit_statistics(ctxt : &Context, all_fit_statistics : &[FitStatistics]) -> String {
+    if (all_fit_statistics.is_empty()) {
+        return "No recorded applications with vector results".to_owned();
+    }
+    let mut result = String::new();
+    let _ = write!(result, "{:>6} {:>8} {:>12} {:>12}", "apps", "skipped", "rmse", "mean_lpd");
+    for coverage in FIT_COVERAGE_LEVELS.iter() {
+        let _ = write!(result, " {:>8}", format!("cov{}", coverage));
+    }
+    let _ = write!(result, "  type");
+    for fit_statistics in all_fit_statistics.iter() {
+        let _ = write!(result, "\n{:>6} {:>8} {:>12} {:>12}", fit_statistics.num_apps, fit_statistics.num_skipped,
+                       format_maybe_statistic(fit_statistics.get_rmse(), 6),
+                       format_maybe_statistic(fit_statistics.get_mean_log_density(), 4));
+        for i in 0..FIT_COVERAGE_LEVELS.len() {
+            let _ = write!(result, " {:>8}", format_maybe_statistic(fit_statistics.get_coverage(i), 3));
+        }
+        let _ = write!(result, "  {}", format_type(ctxt, fit_statistics.func_type_id));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_without_applications_are_none() {
+        let mut fit_statistics = FitStatistics::new(0);
+        fit_statistics.num_skipped = 2;
+        assert_eq!(fit_statistics.get_rmse(), Option::None);
+        assert_eq!(fit_statistics.get_mean_log_density(), Option::None);
+        assert_eq!(fit_statistics.get_coverage(0), Option::None);
+    }
+
+    #[test]
+    fn statistics_average_over_dimensions_and_applications() {
+        let mut fit_statistics = FitStatistics::new(0);
+        let sampled_vectors = SampledVectors {
+            type_id : 0,
+            samples : array![[0.0f32, 0.0], [2.0, 2.0], [4.0, 4.0]],
+            block_size : 1
+        };
+        //The sample mean is 2 in both dimensions, so the errors are 1 and 3
+        fit_statistics.add(array![3.0f32, 5.0].view(), &sampled_vectors);
+        assert_eq!(fit_statistics.get_rmse(), Option::Some(5.0f64.sqrt()));
+        //Only the first dimension falls inside the central 90% of the samples, [0.2, 3.8]
+        assert_eq!(fit_statistics.get_coverage(1), Option::Some(0.5));
+        assert!(fit_statistics.get_mean_log_density().unwrap().is_finite());
+    }
+}
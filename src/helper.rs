@@ -0,0 +1,422 @@
+use std::fs;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{Validator, ValidationContext, ValidationResult};
+use rustyline::Helper;
+use crate::parsers::*;
+use crate::state::*;
+use crate::tokenizer::*;
+
+const BRACKET_MATCH_STYLE : &str = "\x1b[1;7m";
+const UNMATCHED_BRACKET_STYLE : &str = "\x1b[1;31m";
+const TYPE_REFERENCE_STYLE : &str = "\x1b[1;34m";
+const TERM_INDEX_STYLE : &str = "\x1b[36m";
+const PRIMITIVE_NAME_STYLE : &str = "\x1b[32m";
+const UNKNOWN_NAME_STYLE : &str = "\x1b[4;31m";
+const RESET_STYLE : &str = "\x1b[0m";
+
+///Line-editing helper for the REPL. Since the editor can't borrow the GlobalState
+///while commands are mutating it, this keeps a snapshot of everything it needs,
+///which should be refreshed before each line is read.
+pub struct InspectorHelper {
+    identifiers : Vec<String>,
+    primitive_names : Vec<String>,
+    num_types : usize,
+    known_identifiers : HashSet<String>,
+    known_primitive_names : HashSet<String>
+}
+
+impl Default for InspectorHelper {
+    fn default() -> InspectorHelper {
+        InspectorHelper::new()
+    }
+}
+
+impl InspectorHelper {
+    pub fn new() -> InspectorHelper {
+        InspectorHelper {
+            identifiers : Vec::new(),
+            primitive_names : Vec::new(),
+            num_types : 0,
+            known_identifiers : HashSet::new(),
+            known_primitive_names : HashSet::new()
+        }
+    }
+
+    pub fn refresh(&mut self, glob_state : &GlobalState) {
+        self.identifiers = glob_state.bindings.get_identifiers();
+        self.primitive_names.clear();
+        self.num_types = 0;
+        if let Option::Some(context_state) = &glob_state.maybe_context_state {
+            let ctxt = context_state.get_context();
+            self.num_types = ctxt.get_total_num_types();
+            for primitive_type_space in ctxt.primitive_directory.primitive_type_spaces.values() {
+                for term in primitive_type_space.terms.iter() {
+                    self.primitive_names.push(term.get_name());
+                }
+            }
+            self.primitive_names.sort();
+            self.primitive_names.dedup();
+        }
+        self.known_identifiers = self.identifiers.iter().cloned().collect();
+        self.known_primitive_names = self.primitive_names.iter().cloned().collect();
+    }
+
+    fn complete_command(&self, prefix : &str) -> Vec<Pair> {
+        let primitive_commands = COMMANDS.iter().filter(|command| command.maybe_parse_bare.is_some())
+                                 .flat_map(|command| command.names.iter())
+                                 .map(|name| (name.to_string(), name.to_string()));
+        //Commands with arguments get a trailing space, so the user can go right on typing
+        let argumented_commands = COMMANDS.iter().filter(|command| command.maybe_parse_argument.is_some())
+                                  .flat_map(|command| command.names.iter())
+                                  .map(|name| (name.to_string(), format!("{} ", name)));
+        let candidates = primitive_commands.chain(argumented_commands);
+        filter_candidates(prefix, candidates)
+    }
+
+    fn complete_type_number(&self, prefix : &str) -> Vec<Pair> {
+        let candidates = (0..self.num_types).map(|type_id| (type_id.to_string(), type_id.to_string()));
+        filter_candidates(prefix, candidates)
+    }
+
+    fn complete_expression_word(&self, word : &str) -> Vec<Pair> {
+        if (word.starts_with('@')) {
+            let candidates = self.primitive_names.iter().map(|name| (name.clone(), format!("@{}", name)));
+            filter_candidates(word, candidates)
+        } else if (word.starts_with('#')) {
+            let candidates = (0..self.num_types).map(|type_id| (format!("#{}", type_id), format!("#{}", type_id)));
+            filter_candidates(word, candidates)
+        } else {
+            let candidates = self.identifiers.iter().map(|name| (name.clone(), name.clone()));
+            filter_candidates(word, candidates)
+        }
+    }
+}
+
+///Keeps only those (display, replacement) candidates whose replacement extends the given prefix
+fn filter_candidates<I>(prefix : &str, candidates : I) -> Vec<Pair>
+   where I : Iterator<Item = (String, String)> {
+    let mut result : Vec<Pair> = candidates.filter(|(_, replacement)| replacement.starts_with(prefix))
+                                           .map(|(display, replacement)| Pair { display, replacement })
+                                           .collect();
+    result.sort_by(|a, b| a.replacement.cmp(&b.replacement));
+    result.dedup_by(|a, b| a.replacement == b.replacement);
+    result
+}
+
+///Completes a (possibly partial) path, expanding ~ and environment variables via shellexpand
+///to find the directory to look in, but leaving what the user typed untouched in the replacement.
+fn complete_path(word : &str) -> Vec<Pair> {
+    let (dir_part, file_prefix) = match (word.rfind('/')) {
+        Option::Some(slash_index) => word.split_at(slash_index + 1),
+        Option::None => ("", word)
+    };
+    let mut result = Vec::new();
+
+    //Something like ~ or $HOME which names a directory, but lacks a trailing slash
+    if (dir_part.is_empty() && !word.is_empty()) {
+        if let Result::Ok(expanded_word) = shellexpand::full(word) {
+            if (*expanded_word != *word && fs::metadata(&*expanded_word).map(|m| m.is_dir()).unwrap_or(false)) {
+                result.push(Pair {
+                    display : format!("{}/", word),
+                    replacement : format!("{}/", word)
+                });
+                return result;
+            }
+        }
+    }
+
+    let expanded_dir = match (shellexpand::full(dir_part)) {
+        Result::Ok(expanded_dir) => expanded_dir.into_owned(),
+        Result::Err(_) => {
+            return result;
+        }
+    };
+    let search_dir = if (expanded_dir.is_empty()) {".".to_owned()} else {expanded_dir};
+    let entries = match (fs::read_dir(&search_dir)) {
+        Result::Ok(entries) => entries,
+        Result::Err(_) => {
+            return result;
+        }
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        //Hidden files are only offered if the user asks for them
+        if (!file_name.starts_with(file_prefix) || (file_name.starts_with('.') && !file_prefix.starts_with('.'))) {
+            continue;
+        }
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+        let suffix = if (is_dir) {"/"} else {""};
+        result.push(Pair {
+            display : format!("{}{}", file_name, suffix),
+            replacement : format!("{}{}{}", dir_part, file_name, suffix)
+        });
+    }
+    result.sort_by(|a, b| a.replacement.cmp(&b.replacement));
+    result
+}
+
+fn is_expression_word_break(c : char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '[' || c == ']' || c == ',' || c == '='
+}
+
+impl Completer for InspectorHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line : &str, pos : usize, _ctx : &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before_cursor = &line[..pos];
+        let trimmed = before_cursor.trim_start();
+        let command_start = before_cursor.len() - trimmed.len();
+
+        match (trimmed.find(char::is_whitespace)) {
+            Option::None => {
+                Result::Ok((command_start, self.complete_command(trimmed)))
+            },
+            Option::Some(command_len) => {
+                let command = &trimmed[..command_len];
+                let after_command = &trimmed[command_len..];
+                let arg_start = pos - after_command.trim_start().len();
+                let arg = &line[arg_start..pos];
+
+                let maybe_argument_kind = find_command(command).map(|command| command.argument_kind);
+                if (maybe_argument_kind == Option::Some(ArgumentKind::Path)) {
+                    Result::Ok((arg_start, complete_path(arg)))
+                } else if (maybe_argument_kind == Option::Some(ArgumentKind::TypeNumber)) {
+                    Result::Ok((arg_start, self.complete_type_number(arg)))
+                } else {
+                    let word_start = before_cursor.char_indices().rev()
+                                     .find(|(_, c)| is_expression_word_break(*c))
+                                     .map(|(ind, c)| ind + c.len_utf8())
+                                     .unwrap_or(0);
+                    let word = &line[word_start..pos];
+                    Result::Ok((word_start, self.complete_expression_word(word)))
+                }
+            }
+        }
+    }
+}
+
+///Returns the byte offset at which the expression argument of the given line starts,
+///if the line is a command which takes an expression
+fn find_expression_start(line : &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    let command_start = line.len() - trimmed.len();
+    let command_len = trimmed.find(char::is_whitespace)?;
+    let command = &trimmed[..command_len];
+    let command_spec = find_command(command).filter(|command_spec| command_spec.argument_kind == ArgumentKind::Expression)?;
+
+    //Skip over any flags, using the same table as the parser. Until they're complete, there's no expression yet.
+    let after_command = &line[command_start + command_len..];
+    let (_, after_flags) = split_leading_flags(after_command, command_spec.flags).ok()?;
+    let arg_start = line.len() - after_flags.len();
+    if (command == "let") {
+        line[arg_start..].find('=').map(|equals_index| arg_start + equals_index + 1)
+    } else if (command == "trace") {
+        //Skip over the name of the traced command
+        line[arg_start..].find(char::is_whitespace)
+                         .map(|traced_command_len| arg_start + traced_command_len)
+    } else {
+        Option::Some(arg_start)
+    }
+}
+
+///Tokenizes the expression part of the given line [if any], with spans relative to the whole line
+fn tokenize_expression_part(line : &str) -> Vec<Token> {
+    match (find_expression_start(line)) {
+        Option::None => Vec::new(),
+        Option::Some(expr_start) => {
+            let mut tokens = tokenize(&line[expr_start..]);
+            for token in tokens.iter_mut() {
+                token.span = Span::new(token.span.start + expr_start, token.span.end + expr_start);
+            }
+            tokens
+        }
+    }
+}
+
+///For each token index, the index of the token which closes or opens it, if it's a bracket with a partner
+fn match_brackets(tokens : &[Token]) -> Vec<Option<usize>> {
+    let mut partners = vec![Option::None; tokens.len()];
+    let mut open_stack : Vec<usize> = Vec::new();
+    for i in 0..tokens.len() {
+        match (tokens[i].kind) {
+            TokenKind::LeftParen | TokenKind::LeftBracket => {
+                open_stack.push(i);
+            },
+            TokenKind::RightParen | TokenKind::RightBracket => {
+                let expected_open = if (tokens[i].kind == TokenKind::RightParen) {
+                                        TokenKind::LeftParen
+                                    } else {
+                                        TokenKind::LeftBracket
+                                    };
+                if let Option::Some(open_index) = open_stack.last().cloned() {
+                    if (tokens[open_index].kind == expected_open) {
+                        open_stack.pop();
+                        partners[open_index] = Option::Some(i);
+                        partners[i] = Option::Some(open_index);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    partners
+}
+
+///Net number of unclosed left parentheses in the given line. Negative if there are excess right parens.
+pub fn paren_depth(line : &str) -> i64 {
+    let mut depth = 0;
+    for token in tokenize_expression_part(line) {
+        match (token.kind) {
+            TokenKind::LeftParen => depth += 1,
+            TokenKind::RightParen => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn is_bracket(kind : TokenKind) -> bool {
+    matches!(kind, TokenKind::LeftParen | TokenKind::RightParen | TokenKind::LeftBracket | TokenKind::RightBracket)
+}
+
+impl InspectorHelper {
+    ///Determines the style of each token in the expression part of the given line
+    fn style_tokens(&self, tokens : &[Token], line : &str, pos : usize) -> Vec<Option<&'static str>> {
+        let partners = match_brackets(tokens);
+
+        //The bracket immediately at or before the cursor gets highlighted along with its partner
+        let cursor_bracket = (0..tokens.len()).find(|i| is_bracket(tokens[*i].kind) && tokens[*i].span.start == pos)
+                   .or_else(|| (0..tokens.len()).find(|i| is_bracket(tokens[*i].kind) && tokens[*i].span.end == pos));
+
+        let mut styles = vec![Option::None; tokens.len()];
+        for i in 0..tokens.len() {
+            let token = &tokens[i];
+            let previous = if (i > 0) {Option::Some(&tokens[i - 1])} else {Option::None};
+            let adjacent_to_previous = previous.map(|prev| prev.span.end == token.span.start).unwrap_or(false);
+            let previous_kind = previous.map(|prev| prev.kind);
+
+            styles[i] = match (token.kind) {
+                TokenKind::LeftParen | TokenKind::RightParen | TokenKind::LeftBracket | TokenKind::RightBracket => {
+                    match (partners[i]) {
+                        Option::None => Option::Some(UNMATCHED_BRACKET_STYLE),
+                        Option::Some(partner) => {
+                            if (cursor_bracket == Option::Some(i) || cursor_bracket == Option::Some(partner)) {
+                                Option::Some(BRACKET_MATCH_STYLE)
+                            } else {
+                                Option::None
+                            }
+                        }
+                    }
+                },
+                TokenKind::Pound => Option::Some(TYPE_REFERENCE_STYLE),
+                TokenKind::Number if adjacent_to_previous && previous_kind == Option::Some(TokenKind::Pound) => {
+                    Option::Some(TYPE_REFERENCE_STYLE)
+                },
+                TokenKind::At => Option::Some(PRIMITIVE_NAME_STYLE),
+                TokenKind::Word => {
+                    let text = token.text(line);
+                    if (adjacent_to_previous && previous_kind == Option::Some(TokenKind::Number)) {
+                        Option::Some(TERM_INDEX_STYLE)
+                    } else if (adjacent_to_previous && previous_kind == Option::Some(TokenKind::At)) {
+                        if (self.known_primitive_names.contains(text)) {
+                            Option::Some(PRIMITIVE_NAME_STYLE)
+                        } else {
+                            Option::Some(UNKNOWN_NAME_STYLE)
+                        }
+                    } else if (self.known_identifiers.contains(text)) {
+                        Option::None
+                    } else {
+                        Option::Some(UNKNOWN_NAME_STYLE)
+                    }
+                },
+                _ => Option::None
+            };
+        }
+        styles
+    }
+}
+
+impl Highlighter for InspectorHelper {
+    fn highlight<'l>(&self, line : &'l str, pos : usize) -> Cow<'l, str> {
+        let tokens = tokenize_expression_part(line);
+        let styles = self.style_tokens(&tokens, line, pos);
+        if (styles.iter().all(|style| style.is_none())) {
+            return Cow::Borrowed(line);
+        }
+        let mut result = String::with_capacity(line.len() * 2);
+        let mut offset = 0;
+        for (token, maybe_style) in tokens.iter().zip(styles.iter()) {
+            if let Option::Some(style) = maybe_style {
+                result.push_str(&line[offset..token.span.start]);
+                result.push_str(style);
+                result.push_str(token.text(line));
+                result.push_str(RESET_STYLE);
+                offset = token.span.end;
+            }
+        }
+        result.push_str(&line[offset..]);
+        Cow::Owned(result)
+    }
+
+    fn highlight_char(&self, _line : &str, _pos : usize) -> bool {
+        //Bracket matching depends on the cursor position, so every movement may change highlighting
+        true
+    }
+}
+
+impl Validator for InspectorHelper {
+    fn validate(&self, ctx : &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        //Keep reading lines while there are unclosed parens. Excess right parens are left for the parser to report.
+        if (paren_depth(ctx.input()) > 0) {
+            Result::Ok(ValidationResult::Incomplete)
+        } else {
+            Result::Ok(ValidationResult::Valid(Option::None))
+        }
+    }
+}
+
+impl Hinter for InspectorHelper {
+    type Hint = String;
+}
+
+impl Helper for InspectorHelper {
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expression_part(line : &str) -> Option<&str> {
+        find_expression_start(line).map(|expr_start| line[expr_start..].trim())
+    }
+
+    #[test]
+    fn expression_starts_after_command_specific_syntax() {
+        assert_eq!(expression_part("eval (f x)"), Option::Some("(f x)"));
+        assert_eq!(expression_part("let y = (f x)"), Option::Some("(f x)"));
+        assert_eq!(expression_part("trace eval (f x)"), Option::Some("(f x)"));
+        assert_eq!(expression_part("load_context ~/ctxt.json"), Option::None);
+        assert_eq!(expression_part("help"), Option::None);
+    }
+
+    #[test]
+    fn expression_starts_after_flags() {
+        assert_eq!(expression_part("sim -n 100 --mean (f x)"), Option::Some("(f x)"));
+        assert_eq!(expression_part("sim -s sobol --consistent -q 0.1,0.9 x"), Option::Some("x"));
+        assert_eq!(expression_part("compare -q 0.05,0.95 -n 10 (f x)"), Option::Some("(f x)"));
+        assert_eq!(expression_part("neighbors -m cosine #3n2 5"), Option::Some("#3n2 5"));
+        //Incomplete flags aren't mistaken for the start of an expression
+        assert_eq!(expression_part("sim -n"), Option::None);
+        assert_eq!(expression_part("neighbors -q x"), Option::None);
+    }
+
+    #[test]
+    fn paren_depth_ignores_flags() {
+        assert_eq!(paren_depth("sim -n 3 (f (g"), 2);
+        assert_eq!(paren_depth("compare -q 0.1,0.9 (f x))"), -1);
+    }
+}
@@ -0,0 +1,225 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use crate::error::*;
+
+pub const DEFAULT_HISTORY_BASE_PATH : &str = "~/.fetish_inspector_history";
+pub const DEFAULT_HISTORY_SIZE : usize = 1000;
+
+///Environment variable overriding DEFAULT_HISTORY_BASE_PATH
+pub const HISTORY_PATH_VAR : &str = "FETISH_INSPECTOR_HISTORY";
+///Environment variable overriding DEFAULT_HISTORY_SIZE
+pub const HISTORY_SIZE_VAR : &str = "FETISH_INSPECTOR_HISTORY_SIZE";
+
+///History of command lines entered at the REPL, persisted to a file
+///with one entry per line [with newlines and backslashes escaped]
+pub struct CommandHistory {
+    maybe_path : Option<String>,
+    max_size : usize,
+    entries : Vec<String>
+}
+
+///64-bit FNV-1a, used instead of the std hasher since file names must be stable across builds
+fn stable_hash(bytes : &[u8]) -> u64 {
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+///Determines the history file for the context generator library at the given path. Each library
+///gets its own file, since commands for one set of contexts are rarely useful with another.
+pub fn history_path_for_library(base_path : &str, lib_path : &str) -> Option<String> {
+    let expanded_base = shellexpand::full(base_path).ok()?;
+    let canonical_lib_path = fs::canonicalize(lib_path)
+                             .map(|path| path.to_string_lossy().into_owned())
+                             .unwrap_or_else(|_| lib_path.to_owned());
+    let lib_stem = Path::new(&canonical_lib_path).file_stem()
+                   .map(|stem| stem.to_string_lossy().into_owned())
+                   .unwrap_or_default();
+    let sanitized_stem : String = lib_stem.chars()
+                                  .map(|c| if (c.is_alphanumeric() || c == '_' || c == '-') {c} else {'_'})
+                                  .collect();
+    let hash = stable_hash(canonical_lib_path.as_bytes());
+    Option::Some(format!("{}.{}.{:016x}", expanded_base, sanitized_stem, hash))
+}
+
+fn escape_entry(entry : &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_entry(line : &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Option::Some(c) = chars.next() {
+        if (c == '\\') {
+            match (chars.next()) {
+                Option::Some('n') => result.push('\n'),
+                Option::Some(other) => result.push(other),
+                Option::None => result.push('\\')
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+impl CommandHistory {
+    ///History which lives only as long as the session
+    pub fn in_memory(max_size : usize) -> CommandHistory {
+        CommandHistory {
+            maybe_path : Option::None,
+            max_size,
+            entries : Vec::new()
+        }
+    }
+
+    ///History for the library at the given path, with the location and size limit
+    ///taken from the environment if set there
+    pub fn for_library(lib_path : &str) -> CommandHistory {
+        let base_path = env::var(HISTORY_PATH_VAR).unwrap_or_else(|_| DEFAULT_HISTORY_BASE_PATH.to_owned());
+        let max_size = env::var(HISTORY_SIZE_VAR).ok()
+                       .and_then(|size_text| size_text.trim().parse::<usize>().ok())
+                       .unwrap_or(DEFAULT_HISTORY_SIZE);
+        let mut result = CommandHistory::in_memory(max_size);
+        result.maybe_path = history_path_for_library(&base_path, lib_path);
+        result.load();
+        result
+    }
+
+    fn load(&mut self) {
+        if let Option::Some(path) = &self.maybe_path {
+            //A missing history file just means that this is the first session
+            if let Result::Ok(contents) = fs::read_to_string(path) {
+                self.entries = contents.lines().map(unescape_entry).collect();
+                self.truncate();
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), InspectorError> {
+        match (&self.maybe_path) {
+            Option::None => Result::Ok(()),
+            Option::Some(path) => {
+                let mut contents = String::new();
+                for entry in self.entries.iter() {
+                    contents.push_str(&escape_entry(entry));
+                    contents.push('\n');
+                }
+                fs::write(path, contents).map_err(|err| InspectorError::io(path, err).with_context("Save History"))
+            }
+        }
+    }
+
+    fn truncate(&mut self) {
+        if (self.entries.len() > self.max_size) {
+            let excess = self.entries.len() - self.max_size;
+            self.entries.drain(0..excess);
+        }
+    }
+
+    ///Records the given line, ignoring blank lines and immediate repeats
+    pub fn push(&mut self, line : &str) {
+        if (line.trim().is_empty() || self.entries.last().map(|last| last == line).unwrap_or(false)) {
+            return;
+        }
+        self.entries.push(line.to_owned());
+        self.truncate();
+    }
+
+    pub fn get_entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn get_max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.maybe_path.as_deref()
+    }
+
+    ///All (index, entry) pairs whose entries contain the given text
+    pub fn search<'a>(&'a self, query : &str) -> Vec<(usize, &'a String)> {
+        self.entries.iter().enumerate()
+                    .filter(|(_, entry)| entry.contains(query))
+                    .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping_round_trips() {
+        for entry in &["sim #3p0", "let x = \\", "a\\nb", "two\nlines", "\\\n\\", "trailing \\", ""] {
+            let escaped = escape_entry(entry);
+            assert!(!escaped.contains('\n'), "{:?} escaped to {:?}", entry, escaped);
+            assert_eq!(unescape_entry(&escaped), *entry);
+        }
+        assert_eq!(escape_entry("a\\b\nc"), "a\\\\b\\nc");
+        //A lone trailing backslash [e.g. from a hand-edited file] is kept as it is
+        assert_eq!(unescape_entry("abc\\"), "abc\\");
+    }
+
+    #[test]
+    fn push_skips_blank_lines_and_immediate_repeats() {
+        let mut history = CommandHistory::in_memory(10);
+        history.push("eval x");
+        history.push("   ");
+        history.push("");
+        history.push("eval x");
+        history.push("eval y");
+        history.push("eval x");
+        assert_eq!(history.get_entries(), &["eval x", "eval y", "eval x"]);
+    }
+
+    #[test]
+    fn push_drops_the_oldest_entries() {
+        let mut history = CommandHistory::in_memory(2);
+        for line in &["a", "b", "c"] {
+            history.push(line);
+        }
+        assert_eq!(history.get_entries(), &["b", "c"]);
+    }
+
+    #[test]
+    fn search_finds_matching_entries_with_their_indices() {
+        let mut history = CommandHistory::in_memory(10);
+        for line in &["eval (f x)", "list_types", "sim (f y)"] {
+            history.push(line);
+        }
+        let found : Vec<(usize, &str)> = history.search("(f").into_iter().map(|(i, entry)| (i, entry.as_str())).collect();
+        assert_eq!(found, vec![(0, "eval (f x)"), (2, "sim (f y)")]);
+        assert!(history.search("neighbors").is_empty());
+    }
+
+    #[test]
+    fn saved_history_reloads_truncated_to_the_configured_size() {
+        let base_path = env::temp_dir().join(format!("fetish_inspector_history_test_{}", std::process::id()));
+        env::set_var(HISTORY_PATH_VAR, base_path.to_string_lossy().as_ref());
+        env::set_var(HISTORY_SIZE_VAR, "3");
+
+        let mut history = CommandHistory::for_library("no/such/libcontext.so");
+        assert_eq!(history.get_max_size(), 3);
+        for line in &["a", "b", "multi\nline \\", "d", "e"] {
+            history.push(line);
+        }
+        history.save().unwrap();
+
+        env::set_var(HISTORY_SIZE_VAR, "2");
+        let reloaded = CommandHistory::for_library("no/such/libcontext.so");
+        let path = reloaded.get_path().unwrap().to_owned();
+        env::remove_var(HISTORY_PATH_VAR);
+        env::remove_var(HISTORY_SIZE_VAR);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(history.get_entries(), &["multi\nline \\", "d", "e"]);
+        assert_eq!(reloaded.get_entries(), &["d", "e"]);
+        assert!(path.contains("libcontext"), "{}", path);
+    }
+}
@@ -2,6 +2,7 @@ extern crate libloading;
 use std::env;
 use libloading::{Library, Symbol};
 use fetish_lib::everything::*;
+use crate::error::*;
 
 type GenerateContextFunc = unsafe fn(&[u8]) -> Result<Vec<u8>, String>;
 type DeserializeContextFunc = unsafe fn(&[u8]) -> Result<Context, String>;
@@ -12,26 +13,28 @@ pub struct ContextDefinitionLibraryHandle<'a> {
 }
 
 impl <'a> ContextDefinitionLibraryHandle<'a> {
-    pub fn new(lib : &'a Library) -> Result<ContextDefinitionLibraryHandle<'a>, libloading::Error> {
+    pub fn new(lib : &'a Library) -> Result<ContextDefinitionLibraryHandle<'a>, InspectorError> {
         unsafe {
-            let generate_context_symbol : Symbol<GenerateContextFunc> = lib.get(b"generate_serialized_context")?;
-            let deserialize_context_symbol : Symbol<DeserializeContextFunc> = lib.get(b"deserialize_serialized_context")?;
+            let generate_context_symbol : Symbol<GenerateContextFunc> = lib.get(b"generate_serialized_context")
+                .map_err(|err| InspectorError::LibraryLoading(err.to_string()))?;
+            let deserialize_context_symbol : Symbol<DeserializeContextFunc> = lib.get(b"deserialize_serialized_context")
+                .map_err(|err| InspectorError::LibraryLoading(err.to_string()))?;
             Result::Ok(ContextDefinitionLibraryHandle {
                 generate_context_symbol,
                 deserialize_context_symbol
             })
         }
     }
-    pub fn generate_serialized_context(&self, param_json_bytes : &[u8]) -> Result<Vec<u8>, String> {
+    pub fn generate_serialized_context(&self, param_json_bytes : &[u8]) -> Result<Vec<u8>, InspectorError> {
         unsafe {
             let generate_context = &self.generate_context_symbol;
-            generate_context(param_json_bytes)
+            generate_context(param_json_bytes).map_err(InspectorError::ContextGeneration)
         }
     }
-    pub fn deserialize_serialized_context(&self, context_bytes : &[u8]) -> Result<Context, String> {
+    pub fn deserialize_serialized_context(&self, context_bytes : &[u8]) -> Result<Context, InspectorError> {
         unsafe {
             let deserialize_context = &self.deserialize_context_symbol;
-            deserialize_context(context_bytes)
+            deserialize_context(context_bytes).map_err(|err| InspectorError::Serialization(format!("Context: {}", err)))
         }
     }
 }
@@ -8,6 +8,7 @@ extern crate libloading;
 extern crate ndarray_linalg;
 extern crate pretty_env_logger;
 extern crate fetish_lib;
+extern crate rayon;
 #[macro_use] extern crate log;
 
 use ndarray::*;
@@ -16,76 +17,201 @@ use fetish_lib::everything::*;
 use crate::expression::*;
 use crate::parsers::*;
 use crate::bindings::*;
-use std::env;
+use std::process;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use log::LevelFilter;
+use clap::{App, Arg, ArgGroup, ArgMatches};
 use libloading::{Library, Symbol};
 use crate::loading::*;
 use crate::state::*;
 use crate::commands::*;
+use crate::helper::*;
+use crate::history::*;
+use crate::script::*;
+use crate::error::*;
 
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::{Config, Editor};
 
 pub mod state;
 pub mod commands;
 pub mod parsers;
+pub mod tokenizer;
 pub mod expression;
 pub mod bindings;
 pub mod loading;
 pub mod simulate;
+pub mod helper;
+pub mod history;
+pub mod typecheck;
+pub mod script;
+pub mod error;
+pub mod trace;
+pub mod applications;
+pub mod terms;
+pub mod statistics;
+pub mod qmc;
+pub mod compare;
+pub mod fit;
+pub mod neighbors;
 
-fn main() {
-    let args : Vec<String> = env::args().collect();
-    if (args.len() < 2) {
-        println!("Usage: first and only argument is path to context generator dylib");
-        return;
+fn build_cli() -> App<'static, 'static> {
+    App::new("fetish-inspector")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .after_help("Without --script, commands are read from stdin if it isn't a terminal, and interactively otherwise")
+        .arg(Arg::with_name("library")
+             .help("Path to the context generator dylib")
+             .required(true)
+             .index(1))
+        .arg(Arg::with_name("context")
+             .long("context")
+             .value_name("PATH")
+             .takes_value(true)
+             .help("Loads a json-ized Context from the given path on startup"))
+        .arg(Arg::with_name("params")
+             .long("params")
+             .value_name("PATH")
+             .takes_value(true)
+             .help("Generates a Context from the json-ized Params at the given path on startup"))
+        //At most one of these may be given
+        .group(ArgGroup::with_name("context-source")
+               .args(&["context", "params"]))
+        .arg(Arg::with_name("models")
+             .long("models")
+             .value_name("PATH")
+             .takes_value(true)
+             .requires("context-source")
+             .help("Loads the interpreter+embedder state from the given path on startup [requires --context or --params]"))
+        .arg(Arg::with_name("bindings")
+             .long("bindings")
+             .value_name("PATH")
+             .takes_value(true)
+             .help("Loads json-ized variable bindings from the given path on startup"))
+        .arg(Arg::with_name("script")
+             .long("script")
+             .value_name("PATH")
+             .takes_value(true)
+             .help("Runs the commands in the given file, exiting with an error on the first failing command"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .value_name("U64")
+             .takes_value(true)
+             .validator(|seed_text| seed_text.parse::<u64>().map(|_| ()).map_err(|err| err.to_string()))
+             .help("Seeds the random number generator used for simulation [random by default]"))
+        .arg(Arg::with_name("sobol-directions")
+             .long("sobol-directions")
+             .value_name("PATH")
+             .takes_value(true)
+             .help("Loads Joe-Kuo direction numbers for sobol sampling from the given path on startup"))
+        .arg(Arg::with_name("log-level")
+             .long("log-level")
+             .value_name("LEVEL")
+             .takes_value(true)
+             .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+             .help("Sets the logging level [overrides RUST_LOG]"))
+}
+
+fn init_logging(maybe_log_level : Option<&str>) {
+    match (maybe_log_level.and_then(|level_text| LevelFilter::from_str(level_text).ok())) {
+        Option::Some(level) => {
+            pretty_env_logger::formatted_builder().filter_level(level).init();
+        },
+        Option::None => {
+            pretty_env_logger::init();
+        }
     }
+}
 
-    let context_generator_path = &args[1];
+///Runs the commands requested through command-line flags before the session begins
+fn run_startup_commands<'a>(matches : &ArgMatches, glob_state : &mut GlobalState<'a>) -> Result<(), InspectorError> {
+    let mut startup_commands = Vec::new();
+    if let Option::Some(params_path) = matches.value_of("params") {
+        startup_commands.push(Command::GenerateContextFromPath(params_path.to_owned()));
+    }
+    if let Option::Some(context_path) = matches.value_of("context") {
+        startup_commands.push(Command::LoadContextFromPath(context_path.to_owned()));
+    }
+    //Loading models clears all bindings, so bindings have to come after
+    if let Option::Some(models_path) = matches.value_of("models") {
+        startup_commands.push(Command::Contextual(ContextualCommand::LoadModelsFromPath(models_path.to_owned())));
+    }
+    if let Option::Some(bindings_path) = matches.value_of("bindings") {
+        startup_commands.push(Command::LoadBindingsFromPath(bindings_path.to_owned()));
+    }
+    if let Option::Some(sobol_directions_path) = matches.value_of("sobol-directions") {
+        startup_commands.push(Command::LoadSobolDirectionsFromPath(sobol_directions_path.to_owned()));
+    }
+    for command in startup_commands {
+        handle_command_catching_panics(command, glob_state)?;
+    }
+    Result::Ok(())
+}
+
+fn main() {
+    let matches = build_cli().get_matches();
+    init_logging(matches.value_of("log-level"));
+
+    let context_generator_path = matches.value_of("library").unwrap_or_default().to_owned();
+    let maybe_script_path = matches.value_of("script").map(|path| path.to_owned());
 
     let bindings = Bindings::new();
+    //The seed is chosen up-front even when not given, so that it can be reported with seed
+    let seed = matches.value_of("seed").and_then(|seed_text| seed_text.parse::<u64>().ok())
+                      .unwrap_or_else(|| rand::thread_rng().gen());
 
     let maybe_lib = unsafe {
-        Library::new(context_generator_path)
+        Library::new(&context_generator_path)
     };
     
     match (maybe_lib) {
         Result::Err(err) => {
-            println!("Error occurred when loading context generation library: {}", err);
-            return;
+            println!("{}", InspectorError::LibraryLoading(err.to_string()));
+            process::exit(1);
         },
         Result::Ok(context_generation_lib) => {
             let maybe_lib_handle = ContextDefinitionLibraryHandle::new(&context_generation_lib);
             match (maybe_lib_handle) {
                 Result::Err(err) => {
-                    println!("Error occurred while locating context generation library symbols: {}", err);
-                    return;
+                    println!("{}", err);
+                    process::exit(1);
                 },
                 Result::Ok(lib_handle) => {
+                    let is_interactive = maybe_script_path.is_none() && atty::is(atty::Stream::Stdin);
+                    let history = if (is_interactive) {
+                                      CommandHistory::for_library(&context_generator_path)
+                                  } else {
+                                      CommandHistory::in_memory(DEFAULT_HISTORY_SIZE)
+                                  };
                     let mut glob_state = GlobalState {
                         bindings,
                         lib_handle,
-                        maybe_context_state : Option::None
+                        maybe_context_state : Option::None,
+                        history,
+                        source_depth : 0,
+                        trace_evaluation : false,
+                        maybe_sobol_directions : Option::None,
+                        seed,
+                        rng : StdRng::seed_from_u64(seed)
                     };
-                    let mut rl = Editor::<()>::new();
-                    loop {
-                        let readline = rl.readline(">> ");
-                        match readline {
-                            Ok(line) => {
-                                parse_and_handle_command(line.as_str(), &mut glob_state);
-                                rl.add_history_entry(line.as_str());
-                            },
-                            Err(ReadlineError::Interrupted) => {
-                                println!("CTRL-C");
-                                break
-                            },
-                            Err(ReadlineError::Eof) => {
-                                println!("CTRL-D");
-                                break
-                            },
-                            Err(err) => {
-                                println!("Error: {:?}", err);
-                                break
-                            }
+
+                    if let Result::Err(err) = run_startup_commands(&matches, &mut glob_state) {
+                        eprintln!("Startup: {}", err);
+                        process::exit(1);
+                    }
+
+                    if (is_interactive) {
+                        run_repl(&mut glob_state);
+                    } else {
+                        let script_result = match (&maybe_script_path) {
+                            Option::Some(script_path) => run_script_from_path(script_path, &mut glob_state),
+                            Option::None => run_script_from_stdin(&mut glob_state)
+                        };
+                        if let Result::Err(err) = script_result {
+                            eprintln!("{}", err);
+                            process::exit(1);
                         }
                     }
                 }
@@ -94,14 +220,104 @@ fn main() {
     }
 }
 
-pub fn parse_and_handle_command<'a>(line : &str, glob_state : &mut GlobalState<'a>) {
-    let maybe_command = parse_command_line(line);
-    match (maybe_command) {
-        Result::Ok(command) => {
-            command.handle_command(glob_state);
-        },
-        Result::Err(command_parsing_error) => {
-            println!("Command parsing error: {}", command_parsing_error);
+fn run_repl<'a>(glob_state : &mut GlobalState<'a>) {
+    let config = Config::builder()
+                 .max_history_size(glob_state.history.get_max_size())
+                 .history_ignore_dups(true)
+                 .build();
+    let mut rl = Editor::<InspectorHelper>::with_config(config);
+    rl.set_helper(Option::Some(InspectorHelper::new()));
+    for entry in glob_state.history.get_entries() {
+        rl.add_history_entry(entry.as_str());
+    }
+    loop {
+        if let Option::Some(helper) = rl.helper_mut() {
+            helper.refresh(&*glob_state);
         }
+        let readline = rl.readline(">> ");
+        match readline {
+            Ok(line) => {
+                if let Result::Err(err) = parse_and_handle_command(line.as_str(), glob_state) {
+                    println!("{}", err);
+                }
+                rl.add_history_entry(line.as_str());
+                glob_state.history.push(line.as_str());
+                if let Result::Err(err) = glob_state.history.save() {
+                    println!("{}", err);
+                }
+            },
+            Err(ReadlineError::Interrupted) => {
+                println!("CTRL-C");
+                break
+            },
+            Err(ReadlineError::Eof) => {
+                println!("CTRL-D");
+                break
+            },
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break
+            }
+        }
+    }
+}
+
+//Panics [from this crate, fetish-lib, or the context generator library] are caught here
+//and reported like any other command error, so that they don't end the session.
+//While the command runs, the panic hook is swapped for one which only records where the
+//first panic happened, since the default hook's message would duplicate the reported error.
+pub fn handle_command_catching_panics<'a>(command : Command, glob_state : &mut GlobalState<'a>) -> Result<(), InspectorError> {
+    let maybe_panic_location : Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(Option::None));
+    let hook_panic_location = Arc::clone(&maybe_panic_location);
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |panic_info| {
+        debug!("{}", panic_info);
+        if let Result::Ok(mut maybe_location) = hook_panic_location.lock() {
+            if (maybe_location.is_none()) {
+                *maybe_location = panic_info.location().map(|location| location.to_string());
+            }
+        }
+    }));
+    let maybe_result = panic::catch_unwind(AssertUnwindSafe(|| command.handle_command(glob_state)));
+    //Dropping the quiet hook also drops its reference to the recorded location
+    drop(panic::take_hook());
+    panic::set_hook(previous_hook);
+
+    match (maybe_result) {
+        Result::Ok(result) => result,
+        Result::Err(panic_payload) => {
+            let maybe_location = maybe_panic_location.lock().ok().and_then(|mut maybe_location| maybe_location.take());
+            let models_reset = glob_state.maybe_context_state.as_mut()
+                                         .map(|context_state| context_state.take_models_reset_by_panic())
+                                         .unwrap_or(false);
+            Result::Err(InspectorError::from_panic(panic_payload, maybe_location, models_reset))
+        }
+    }
+}
+
+pub fn parse_and_handle_command<'a>(line : &str, glob_state : &mut GlobalState<'a>) -> Result<(), InspectorError> {
+    let command = parse_command_line(line)?;
+    handle_command_catching_panics(command, glob_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_require_a_context() {
+        let maybe_matches = build_cli().get_matches_from_safe(vec!["fetish-inspector", "libctxt.so", "--models", "m.bin"]);
+        assert_eq!(maybe_matches.err().map(|err| err.kind), Option::Some(clap::ErrorKind::MissingRequiredArgument));
+
+        for context_flag in &["--context", "--params"] {
+            let args = vec!["fetish-inspector", "libctxt.so", context_flag, "c.json", "--models", "m.bin"];
+            assert!(build_cli().get_matches_from_safe(args).is_ok());
+        }
+    }
+
+    #[test]
+    fn context_and_params_conflict() {
+        let args = vec!["fetish-inspector", "libctxt.so", "--context", "c.json", "--params", "p.json"];
+        assert!(build_cli().get_matches_from_safe(args).is_err());
     }
 }
@@ -0,0 +1,249 @@
+use fetish_lib::everything::*;
+use ndarray::*;
+use crate::bindings::*;
+use crate::parsers::*;
+use crate::expression::*;
+use crate::error::*;
+use crate::terms::*;
+use crate::applications::*;
+
+pub const DEFAULT_NUM_NEIGHBORS : usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DistanceMetric {
+    Cosine,
+    Euclidean
+}
+
+pub const DISTANCE_METRIC_NAMES : &[&str] = &["cosine", "euclidean"];
+
+impl DistanceMetric {
+    pub fn from_name(name : &str) -> Option<DistanceMetric> {
+        match (name) {
+            "cosine" => Option::Some(DistanceMetric::Cosine),
+            "euclidean" => Option::Some(DistanceMetric::Euclidean),
+            _ => Option::None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match (self) {
+            DistanceMetric::Cosine => "cosine",
+            DistanceMetric::Euclidean => "euclidean"
+        }
+    }
+
+    pub fn distance(&self, a : ArrayView1<f32>, b : ArrayView1<f32>) -> f32 {
+        match (self) {
+            DistanceMetric::Cosine => {
+                let norms = a.dot(&a).sqrt() * b.dot(&b).sqrt();
+                //A zero vector points nowhere, so it's taken to be orthogonal to everything
+                if (norms > 0.0) {
+                    1.0 - a.dot(&b) / norms
+                } else {
+                    1.0
+                }
+            },
+            DistanceMetric::Euclidean => {
+                let difference = &a - &b;
+                difference.dot(&difference).sqrt()
+            }
+        }
+    }
+}
+
+///Which term to find the nearest neighbors of, and how
+pub struct NeighborQuery {
+    pub term_ptr : TermPointer,
+    pub num_neighbors : usize,
+    pub metric : DistanceMetric
+}
+
+impl NeighborQuery {
+    ///Parses [-m cosine|euclidean] [term] [k], where k is optional
+    pub fn parse(text : &str, bindings : &Bindings, zelf : &InterpreterAndEmbedderState) -> Result<NeighborQuery, InspectorError> {
+        NeighborQuery::parse_with(text, &|term_text| parse_term_ref(term_text, bindings, zelf))
+    }
+
+    ///Like parse, but with terms resolved by the given function
+    fn parse_with(text : &str, resolve_term : &dyn Fn(&str) -> Result<TermReference, InspectorError>) -> Result<NeighborQuery, InspectorError> {
+        let (flags, rest) = split_leading_flags(text, NEIGHBOR_FLAGS)?;
+        let mut metric = DistanceMetric::Cosine;
+        for (flag, maybe_value) in flags {
+            let value = maybe_value.unwrap_or_default();
+            match (flag) {
+                "-m" => {
+                    metric = DistanceMetric::from_name(value)
+                             .ok_or_else(|| InspectorError::Command(format!("-m expects one of {}, not {}",
+                                                                            DISTANCE_METRIC_NAMES.join(", "), value)))?;
+                },
+                _ => unreachable!()
+            }
+        }
+
+        let arguments = split_arguments(rest);
+        let (term_text, num_neighbors) = match (arguments.as_slice()) {
+            [term_text] => (*term_text, DEFAULT_NUM_NEIGHBORS),
+            [term_text, count_text] => {
+                match (count_text.parse::<usize>()) {
+                    Result::Ok(count) if count > 0 => (*term_text, count),
+                    _ => {
+                        return Result::Err(InspectorError::Command(format!("The number of neighbors must be a positive integer, but was {}",
+                                                                           count_text)));
+                    }
+                }
+            },
+            _ => {
+                return Result::Err(InspectorError::Command(format!("Neighbors body {} does not have the format [term] [k]", rest)));
+            }
+        };
+        match (resolve_term(term_text)?) {
+            TermReference::FuncRef(term_ptr) => Result::Ok(NeighborQuery {
+                term_ptr,
+                num_neighbors,
+                metric
+            }),
+            TermReference::VecRef(_, _) => {
+                Result::Err(InspectorError::Command(format!("{} is a vector, so it has no embedding", term_text)))
+            }
+        }
+    }
+}
+
+///The other embedded terms of the query term's type, nearest first, paired with their distances
+pub fn find_neighbors(embedder_state : &EmbedderState, query : &NeighborQuery) -> Result<Vec<(TermPointer, f32)>, InspectorError> {
+    let type_id = query.term_ptr.type_id;
+    let models = match (embedder_state.model_spaces.get(&type_id)) {
+        Option::Some(model_space) => &model_space.models,
+        Option::None => {
+            return Result::Err(InspectorError::resolution(format!("No model space for type #{}", type_id)));
+        }
+    };
+    let term_mean = match (models.get(&query.term_ptr.index)) {
+        Option::Some(model) => model.get_mean_as_vec(),
+        Option::None => {
+            return Result::Err(InspectorError::resolution(format!("{} has no embedding [try update_models]",
+                                                                  format_term_ptr(&query.term_ptr))));
+        }
+    };
+
+    let embeddings = models.iter().map(|(index, model)| (*index, model.get_mean_as_vec()));
+    Result::Ok(rank_neighbors(query, term_mean, embeddings))
+}
+
+///Pairs the given embeddings of terms of the query term's type [other than the query term itself]
+///with their distances from the query term's embedding, keeping the nearest ones, nearest first
+fn rank_neighbors<'m, I>(query : &NeighborQuery, term_mean : ArrayView1<f32>, embeddings : I) -> Vec<(TermPointer, f32)>
+    where I : Iterator<Item = (TermIndex, ArrayView1<'m, f32>)> {
+    let mut result : Vec<(TermPointer, f32)> = embeddings
+        .filter(|(index, _)| *index != query.term_ptr.index)
+        .map(|(index, embedding)| {
+            let term_ptr = TermPointer {
+                type_id : query.term_ptr.type_id,
+                index
+            };
+            (term_ptr, query.metric.distance(term_mean, embedding))
+        })
+        .collect();
+    result.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    result.truncate(query.num_neighbors);
+    result
+}
+
+pub fn render_neighbors(state : &InterpreterState, query : &NeighborQuery, neighbors : &[(TermPointer, f32)]) -> String {
+    if (neighbors.is_empty()) {
+        return format!("{} has no embedded neighbors", format_term_ptr(&query.term_ptr));
+    }
+    let mut lines = Vec::new();
+    lines.push(format!("Nearest {} of {} [{}] by {} distance", neighbors.len(), format_term_ptr(&query.term_ptr),
+                       query.term_ptr.display(state), query.metric.get_name()));
+    for (term_ptr, distance) in neighbors.iter() {
+        lines.push(format!("{:>12.6} {}    [{}]", distance, format_term_ptr(term_ptr), term_ptr.display(state)));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noisy_float::prelude::*;
+
+    fn term(index : usize) -> TermPointer {
+        TermPointer {
+            type_id : 1,
+            index : TermIndex::NonPrimitive(index)
+        }
+    }
+
+    //Resolves x to #1n0 and v to a vector
+    fn resolve_term(term_text : &str) -> Result<TermReference, InspectorError> {
+        match (term_text) {
+            "x" => Result::Ok(TermReference::FuncRef(term(0))),
+            "v" => Result::Ok(TermReference::VecRef(0, Array1::from(vec![r32(1.0)]))),
+            _ => Result::Err(InspectorError::resolution(format!("Unknown term {}", term_text)))
+        }
+    }
+
+    fn assert_close(actual : f32, expected : f32) {
+        assert!((actual - expected).abs() < 1e-6, "Expected {}, but got {}", expected, actual);
+    }
+
+    #[test]
+    fn cosine_distance_known_answers() {
+        let cosine = DistanceMetric::Cosine;
+        assert_close(cosine.distance(array![1.0f32, 0.0].view(), array![0.0f32, 3.0].view()), 1.0);
+        assert_close(cosine.distance(array![1.0f32, 1.0].view(), array![2.0f32, 2.0].view()), 0.0);
+        assert_close(cosine.distance(array![1.0f32, 0.0].view(), array![-2.0f32, 0.0].view()), 2.0);
+        assert_close(cosine.distance(array![1.0f32, 0.0].view(), array![1.0f32, 1.0].view()), 1.0 - 0.5f32.sqrt());
+    }
+
+    #[test]
+    fn cosine_distance_from_zero_vectors_is_orthogonal() {
+        let cosine = DistanceMetric::Cosine;
+        assert_eq!(cosine.distance(array![0.0f32, 0.0].view(), array![1.0f32, 2.0].view()), 1.0);
+        assert_eq!(cosine.distance(array![1.0f32, 2.0].view(), array![0.0f32, 0.0].view()), 1.0);
+        assert_eq!(cosine.distance(array![0.0f32, 0.0].view(), array![0.0f32, 0.0].view()), 1.0);
+    }
+
+    #[test]
+    fn euclidean_distance_known_answers() {
+        let euclidean = DistanceMetric::Euclidean;
+        assert_close(euclidean.distance(array![0.0f32, 0.0].view(), array![3.0f32, 4.0].view()), 5.0);
+        assert_close(euclidean.distance(array![1.0f32, -1.0, 2.0].view(), array![1.0f32, -1.0, 2.0].view()), 0.0);
+        assert_close(euclidean.distance(array![0.0f32].view(), array![-2.5f32].view()), 2.5);
+    }
+
+    #[test]
+    fn parses_metric_term_and_count() {
+        let query = NeighborQuery::parse_with("x", &resolve_term).ok().unwrap();
+        assert_eq!((query.term_ptr, query.num_neighbors, query.metric), (term(0), DEFAULT_NUM_NEIGHBORS, DistanceMetric::Cosine));
+        let query = NeighborQuery::parse_with("-m euclidean x 3", &resolve_term).ok().unwrap();
+        assert_eq!((query.term_ptr, query.num_neighbors, query.metric), (term(0), 3, DistanceMetric::Euclidean));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for text in &["", "x 0", "x two", "x -1", "x 3 4", "-m manhattan x", "-k 3 x", "-m", "v"] {
+            match (NeighborQuery::parse_with(text, &resolve_term)) {
+                Result::Err(InspectorError::Command(_)) => {},
+                Result::Err(err) => panic!("Expected a command error for {:?}, but got {}", text, err),
+                Result::Ok(_) => panic!("Expected {:?} not to parse", text)
+            }
+        }
+        assert!(matches!(NeighborQuery::parse_with("y 3", &resolve_term), Result::Err(InspectorError::Resolution { .. })));
+    }
+
+    #[test]
+    fn neighbors_are_nearest_first_and_truncated() {
+        let query = NeighborQuery {
+            term_ptr : term(0),
+            num_neighbors : 3,
+            metric : DistanceMetric::Euclidean
+        };
+        let embeddings = [array![0.0f32, 0.0], array![5.0, 0.0], array![0.0, 1.0], array![-3.0, 0.0], array![2.0, 0.0]];
+        let ranked = rank_neighbors(&query, embeddings[0].view(),
+                                    embeddings.iter().enumerate()
+                                              .map(|(index, embedding)| (TermIndex::NonPrimitive(index), embedding.view())));
+        assert_eq!(ranked, vec![(term(2), 1.0), (term(4), 2.0), (term(3), 3.0)]);
+    }
+}
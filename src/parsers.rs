@@ -4,10 +4,128 @@ use noisy_float::prelude::*;
 use crate::expression::*;
 use crate::bindings::*;
 use crate::commands::*;
+use crate::tokenizer::*;
+use crate::error::*;
+use crate::simulate::*;
+use std::fmt;
 
-pub fn parse_command_line(text : &str) -> Result<Command, String> {
+///What a command's argument is, which decides how it's completed and highlighted
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArgumentKind {
+    ///An expression, after any flags [for let, after the =, for trace, after the traced
+    ///command, and for neighbors, followed by the number of neighbors]
+    Expression,
+    ///A filesystem path
+    Path,
+    ///A type number, possibly followed by options
+    TypeNumber,
+    Other
+}
+
+type ParseBareFunc = fn() -> Result<Command, InspectorError>;
+type ParseArgumentFunc = fn(&str) -> Result<Command, InspectorError>;
+
+///A command, under all of its names. Commands may be given bare, with an argument, or either.
+pub struct CommandSpec {
+    pub names : &'static [&'static str],
+    pub maybe_parse_bare : Option<ParseBareFunc>,
+    ///Parses the trimmed text after the command name
+    pub maybe_parse_argument : Option<ParseArgumentFunc>,
+    pub argument_kind : ArgumentKind,
+    ///Flags which may precede the argument, as for split_leading_flags
+    pub flags : &'static [(&'static str, bool)]
+}
+
+///Every command, which both dispatch and completion are driven by
+pub const COMMANDS : &[CommandSpec] = &[
+    CommandSpec { names : &["parse"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Parse(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["generate_context"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::GenerateContextFromPath(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["load_context"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::LoadContextFromPath(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["unload_context"], maybe_parse_bare : Option::Some(|| Result::Ok(Command::UnloadContext)),
+                  maybe_parse_argument : Option::None, argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["list_types"],
+                  maybe_parse_bare : Option::Some(|| Result::Ok(Command::Contextual(ContextualCommand::ListTypes))),
+                  maybe_parse_argument : Option::None, argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["let"], maybe_parse_bare : Option::None, maybe_parse_argument : Option::Some(parse_let),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["evaluate", "eval"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::Evaluate(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["update_models"],
+                  maybe_parse_bare : Option::Some(|| Result::Ok(Command::Contextual(ContextualCommand::UpdateModels))),
+                  maybe_parse_argument : Option::None, argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["simulate", "sim"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(parse_simulate), argument_kind : ArgumentKind::Expression , flags : SIMULATION_FLAGS },
+    CommandSpec { names : &["compare"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(parse_compare), argument_kind : ArgumentKind::Expression , flags : SIMULATION_FLAGS },
+    CommandSpec { names : &["fit_report"],
+                  maybe_parse_bare : Option::Some(|| Result::Ok(Command::Contextual(ContextualCommand::FitReport(SimulationOptions::new())))),
+                  maybe_parse_argument : Option::Some(parse_fit_report), argument_kind : ArgumentKind::Other , flags : FIT_REPORT_FLAGS },
+    CommandSpec { names : &["neighbors"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::Neighbors(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Expression , flags : NEIGHBOR_FLAGS },
+    CommandSpec { names : &["typeof"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::TypeOf(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["list_primitive_terms", "list_prim_terms"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::ListPrimitiveTerms(rest.to_owned())))),
+                  argument_kind : ArgumentKind::TypeNumber , flags : &[] },
+    CommandSpec { names : &["list_terms"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::ListTerms(rest.to_owned())))),
+                  argument_kind : ArgumentKind::TypeNumber , flags : &[] },
+    CommandSpec { names : &["show"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::Show(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["list_apps"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::ListApplications(rest.to_owned())))),
+                  argument_kind : ArgumentKind::TypeNumber , flags : &[] },
+    CommandSpec { names : &["save_context"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::SaveContextToPath(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["load_models"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::LoadModelsFromPath(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["save_models"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Contextual(ContextualCommand::SaveModelsToPath(rest.to_owned())))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["load_bindings"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::LoadBindingsFromPath(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["save_bindings"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::SaveBindingsToPath(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["load_sobol_directions"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::LoadSobolDirectionsFromPath(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["source"], maybe_parse_bare : Option::None,
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::Source(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Path , flags : &[] },
+    CommandSpec { names : &["history"], maybe_parse_bare : Option::Some(|| Result::Ok(Command::History(String::new()))),
+                  maybe_parse_argument : Option::Some(|rest| Result::Ok(Command::History(rest.to_owned()))),
+                  argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["trace"], maybe_parse_bare : Option::None, maybe_parse_argument : Option::Some(parse_trace),
+                  argument_kind : ArgumentKind::Expression , flags : &[] },
+    CommandSpec { names : &["set"], maybe_parse_bare : Option::None, maybe_parse_argument : Option::Some(parse_set),
+                  argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["seed"], maybe_parse_bare : Option::Some(|| Result::Ok(Command::Seed(Option::None))),
+                  maybe_parse_argument : Option::Some(parse_seed), argument_kind : ArgumentKind::Other , flags : &[] },
+    CommandSpec { names : &["help"], maybe_parse_bare : Option::Some(|| Result::Ok(Command::Help)),
+                  maybe_parse_argument : Option::None, argument_kind : ArgumentKind::Other, flags : &[] }
+];
+
+pub fn find_command(name : &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|command| command.names.contains(&name))
+}
+
+pub fn parse_command_line(text : &str) -> Result<Command, InspectorError> {
     let trimmed_text = text.trim();
-    let maybe_split_text = trimmed_text.split_once(' ');
+    let maybe_split_text = trimmed_text.split_once(char::is_whitespace);
     match (maybe_split_text) {
         Option::Some((command_text, rest_text)) => {
             let trimmed_rest = rest_text.trim();
@@ -19,35 +137,31 @@ pub fn parse_command_line(text : &str) -> Result<Command, String> {
     }
 }
 
-pub fn parse_primitive_command(command_text : &str) -> Result<Command, String> {
-    match (command_text) {
-        "unload_context" => Result::Ok(Command::UnloadContext),
-        "list_types" => Result::Ok(Command::Contextual(ContextualCommand::ListTypes)),
-        "update_models" => Result::Ok(Command::Contextual(ContextualCommand::UpdateModels)),
-        "help" => Result::Ok(Command::Help),
-        _ => Result::Err(format!("{} is not a recognized command (without arguments)", command_text))
+pub fn parse_primitive_command(command_text : &str) -> Result<Command, InspectorError> {
+    match (find_command(command_text).and_then(|command| command.maybe_parse_bare)) {
+        Option::Some(parse_bare) => parse_bare(),
+        Option::None => {
+            Result::Err(InspectorError::Command(format!("{} is not a recognized command (without arguments)", command_text)))
+        }
     }
 }
 
-pub fn parse_argumented_command(command_text : &str, trimmed_rest : &str) -> Result<Command, String> {
-    let rest = trimmed_rest.to_owned();
-    match (command_text) {
-        "parse" => Result::Ok(Command::Parse(rest)),
-        "generate_context" => Result::Ok(Command::GenerateContextFromPath(rest)),
-        "load_context" => Result::Ok(Command::LoadContextFromPath(rest)),
-        "let" => parse_let(trimmed_rest),
-        "evaluate" | "eval" => Result::Ok(Command::Contextual(ContextualCommand::Evaluate(rest))),
-        "simulate" | "sim" => Result::Ok(Command::Contextual(ContextualCommand::Simulate(rest))),
-        "list_primitive_terms" | "list_prim_terms" => 
-                        Result::Ok(Command::Contextual(ContextualCommand::ListPrimitiveTerms(rest))),
-        "save_context" => Result::Ok(Command::Contextual(ContextualCommand::SaveContextToPath(rest))),
-        "load_models" => Result::Ok(Command::Contextual(ContextualCommand::LoadModelsFromPath(rest))),
-        "save_models" => Result::Ok(Command::Contextual(ContextualCommand::SaveModelsToPath(rest))),
-        _ => Result::Err(format!("{} is not a recognized command (with arguments)", command_text))
+pub fn parse_argumented_command(command_text : &str, trimmed_rest : &str) -> Result<Command, InspectorError> {
+    match (find_command(command_text).and_then(|command| command.maybe_parse_argument)) {
+        Option::Some(parse_argument) => parse_argument(trimmed_rest),
+        Option::None => {
+            Result::Err(InspectorError::Command(format!("{} is not a recognized command (with arguments)", command_text)))
+        }
     }
 }
 
-pub fn parse_let(let_body_text : &str) -> Result<Command, String> {
+pub fn parse_seed(seed_text : &str) -> Result<Command, InspectorError> {
+    let seed = seed_text.parse::<u64>()
+               .map_err(|err| InspectorError::Command(format!("Unable to parse seed from {}, due to error: {}", seed_text, err)))?;
+    Result::Ok(Command::Seed(Option::Some(seed)))
+}
+
+pub fn parse_let(let_body_text : &str) -> Result<Command, InspectorError> {
     let maybe_split = let_body_text.split_once('=');
     match (maybe_split) {
         Option::Some((untrimmed_var_text, untrimmed_expr_text)) => {
@@ -56,142 +170,734 @@ pub fn parse_let(let_body_text : &str) -> Result<Command, String> {
             Result::Ok(Command::Contextual(ContextualCommand::Let(var_text.to_owned(), expr_text.to_owned())))
         },
         Option::None => 
-            Result::Err(format!("Let body {} does not have the format [var] = [expr]", let_body_text))
+            Result::Err(InspectorError::Command(format!("Let body {} does not have the format [var] = [expr]", let_body_text)))
     }
 }
 
-///(_* [func_atom] [arg_atom_1] ... [arg_atom_n] _*)
-pub fn parse_s_expression<'a>(text : &'a str, bindings : &Bindings) -> Result<(AppExpression, &'a str), String> {
-    let maybe_without_left_paren = text.strip_prefix('(');
-    match (maybe_without_left_paren) {
-        Option::None => Result::Err(format!("Missing left paren for sub-expression: {}", text)),
-        Option::Some(without_left_paren) => {
-            let interior_to_end = without_left_paren.trim_start();
+///Flags which may precede the expression in a simulate command, and whether each takes a value
+pub const SIMULATION_FLAGS : &[(&str, bool)] = &[("-n", true), ("-q", true), ("-o", true), ("--mean", false),
+                                                    ("--consistent", false), ("-s", true)];
 
-            let mut atom_exprs = Vec::new();
-            let mut current_text = interior_to_end;
-            while (!current_text.starts_with(')')) {
-                if (current_text.is_empty()) {
-                    return Result::Err(format!("Ran out of input while parsing s-expression: {}", text));
-                }
-                let (atom_expr, modified_text) = parse_atom(current_text, bindings)?;
-                current_text = modified_text.trim_start();
-                atom_exprs.push(atom_expr);
+///The subset of SIMULATION_FLAGS which make sense for fit_report
+pub const FIT_REPORT_FLAGS : &[(&str, bool)] = &[("-n", true), ("--consistent", false), ("-s", true)];
+
+pub const NEIGHBOR_FLAGS : &[(&str, bool)] = &[("-m", true)];
+
+///Flags, each with its value if it takes one
+pub type Flags<'t> = Vec<(&'t str, Option<&'t str>)>;
+
+///Splits the given flags [and their values] off of the start of the text, returning
+///them along with whatever text remains after them
+pub fn split_leading_flags<'t>(text : &'t str, flags : &[(&str, bool)]) -> Result<(Flags<'t>, &'t str), InspectorError> {
+    let mut result = Vec::new();
+    let mut rest = text.trim_start();
+    while (rest.starts_with('-')) {
+        let (flag, after_flag) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let takes_value = match (flags.iter().find(|(name, _)| *name == flag)) {
+            Option::Some((_, takes_value)) => *takes_value,
+            Option::None => {
+                return Result::Err(InspectorError::Command(format!("Unknown flag {}", flag)));
             }
+        };
+        rest = after_flag.trim_start();
+        if (takes_value) {
+            let (value, after_value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if (value.is_empty()) {
+                return Result::Err(InspectorError::Command(format!("Flag {} expects a value", flag)));
+            }
+            result.push((flag, Option::Some(value)));
+            rest = after_value.trim_start();
+        } else {
+            result.push((flag, Option::None));
+        }
+    }
+    Result::Ok((result, rest))
+}
 
-            current_text = current_text.strip_prefix(')').unwrap();
-
-            let result_expr = build_application(atom_exprs)?;
-            Result::Ok((result_expr, current_text))
-        }
-    }
-}
-
-pub fn parse_reference<'a>(text : &'a str) -> Result<(TermReference, &'a str), String> {
-    let maybe_without_pound_sign = text.strip_prefix('#'); 
-    match (maybe_without_pound_sign) {
-        Option::None => Result::Err(format!("Missing pound sign for reference: {}", text)),
-        Option::Some(without_pound_sign) => {
-            let maybe_first_nonnumeral_index = without_pound_sign.find(|c : char| !c.is_digit(10));
-            let first_nonnumeral_index = maybe_first_nonnumeral_index.unwrap_or(without_pound_sign.len());
-
-            let (prefix_numeral_str, suffix) = without_pound_sign.split_at(first_nonnumeral_index);
-            let type_number : usize = str::parse(prefix_numeral_str).unwrap();
-            if (suffix.starts_with('[')) {
-                let (vec, remaining_text) = parse_vector(suffix)?;
-                Result::Ok((TermReference::VecRef(type_number, vec), remaining_text))
-            } else {
-                let (term_index, remaining_text) = parse_term_index(suffix)?;
-                let term_ptr = TermPointer {
-                    type_id : type_number,
-                    index : term_index
-                };
-                Result::Ok((TermReference::FuncRef(term_ptr), remaining_text))
-            } 
-        }
-    }    
-}
-
-pub fn parse_term_index<'a>(text : &'a str) -> Result<(TermIndex, &'a str), String> {
-    let is_primitive = text.starts_with('p');
-    if (!text.starts_with('p') && !text.starts_with('n')) {
-        return Result::Err(format!("Cannot parse term index {}", text));
-    }
-    let starting_char = if (is_primitive) {'p'} else {'n'};
-    let starting_with_numeral = text.strip_prefix(starting_char).unwrap();
-    let maybe_first_nonnumeral_index = starting_with_numeral.find(|c : char| !c.is_digit(10));
-    let first_nonnumeral_index = maybe_first_nonnumeral_index.unwrap_or(starting_with_numeral.len());
-
-    let (prefix_numeral_str, suffix) = starting_with_numeral.split_at(first_nonnumeral_index);
-    let term_number : usize = str::parse(prefix_numeral_str).unwrap();
-    let term_index = if (is_primitive) {
-                        TermIndex::Primitive(term_number)
-                     } else {
-                        TermIndex::NonPrimitive(term_number)
-                     };
-    Result::Ok((term_index, suffix))
-}
-
-pub fn parse_vector<'a>(text : &'a str) -> Result<(Array1<R32>, &'a str), String> {
-    let maybe_without_left_bracket = text.strip_prefix('[');
-    match (maybe_without_left_bracket) {
-        Option::None => Result::Err(format!("Missing left bracket for vector: {}", text)),
-        Option::Some(without_left_bracket) => {
-            let maybe_right_bracket_index = without_left_bracket.find(']'); 
-            match (maybe_right_bracket_index) {
-                Option::None => Result::Err(format!("Missing right bracket for vector: {}", text)),
-                Option::Some(right_bracket_index) => {
-                    let (vector_content_str, right_bracket_remainder) = 
-                                             without_left_bracket.split_at(right_bracket_index);
-                    let remainder = right_bracket_remainder.strip_prefix(']').unwrap();
-
-                    let mut elems = Vec::new();
-                    for padded_elem_str in vector_content_str.split(',') {
-                        let elem_str = padded_elem_str.trim(); 
-                        let maybe_elem_as_float = str::parse(elem_str);
-                        match (maybe_elem_as_float) {
-                            Result::Err(_) => {
-                                return Result::Err(format!("Malformed float: {}", elem_str));
-                            },
-                            Result::Ok(elem_as_float) => {
-                                let elem = r32(elem_as_float);
-                                elems.push(elem);
-                            }
+///Parses the given flags [a subset of SIMULATION_FLAGS] from the start of the text, returning the rest
+pub fn parse_simulation_options<'t>(body_text : &'t str, flag_table : &[(&str, bool)])
+                                   -> Result<(SimulationOptions, &'t str), InspectorError> {
+    let (flags, expr_text) = split_leading_flags(body_text, flag_table)?;
+    let mut options = SimulationOptions::new();
+    for (flag, maybe_value) in flags {
+        let value = maybe_value.unwrap_or_default();
+        match (flag) {
+            "-n" => {
+                match (value.parse::<usize>()) {
+                    Result::Ok(num_samples) if num_samples > 0 => {
+                        options.maybe_num_samples = Option::Some(num_samples);
+                    },
+                    _ => {
+                        return Result::Err(InspectorError::Command(format!("-n expects a positive sample count, not {}", value)));
+                    }
+                }
+            },
+            "-q" => {
+                let mut quantile_levels = Vec::new();
+                for level_text in value.split(',') {
+                    match (level_text.trim().parse::<f64>()) {
+                        Result::Ok(level) if (0.0..=1.0).contains(&level) => quantile_levels.push(level),
+                        _ => {
+                            return Result::Err(InspectorError::Command(format!("-q expects comma-separated quantiles in [0, 1], not {}", value)));
                         }
                     }
-                    let vec = Array1::from(elems);
-                    Result::Ok((vec, remainder))
                 }
+                options.maybe_quantile_levels = Option::Some(quantile_levels);
+            },
+            "-o" => {
+                options.maybe_output_path = Option::Some(value.to_owned());
+            },
+            "--mean" => {
+                options.at_mean = true;
+            },
+            "--consistent" => {
+                options.consistent = true;
+            },
+            "-s" => {
+                match (SamplingStrategy::from_name(value)) {
+                    Option::Some(strategy) => {
+                        options.strategy = strategy;
+                    },
+                    Option::None => {
+                        return Result::Err(InspectorError::Command(format!("-s expects one of {}, not {}",
+                                                                           SAMPLING_STRATEGY_NAMES.join(", "), value)));
+                    }
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+    Result::Ok((options, expr_text))
+}
+
+pub fn parse_simulate(simulate_body_text : &str) -> Result<Command, InspectorError> {
+    let (options, expr_text) = parse_simulation_options(simulate_body_text, SIMULATION_FLAGS)?;
+    if (options.at_mean && (options.maybe_num_samples.is_some() || options.strategy != SamplingStrategy::Plain)) {
+        return Result::Err(InspectorError::Command("--mean is deterministic, so it can't be combined with -n or -s".to_owned()));
+    }
+    if (options.maybe_quantile_levels.is_some() && options.maybe_num_samples.is_none()) {
+        return Result::Err(InspectorError::Command("-q summarizes many samples, so it needs -n".to_owned()));
+    }
+    Result::Ok(Command::Contextual(ContextualCommand::Simulate(options, expr_text.to_owned())))
+}
+
+pub fn parse_compare(compare_body_text : &str) -> Result<Command, InspectorError> {
+    let (options, expr_text) = parse_simulation_options(compare_body_text, SIMULATION_FLAGS)?;
+    if (options.at_mean) {
+        return Result::Err(InspectorError::Command("compare needs samples for its predictive intervals, so it can't be combined with --mean".to_owned()));
+    }
+    let quantile_levels = options.get_quantile_levels();
+    let has_interval = quantile_levels.iter().any(|level| *level != quantile_levels[0]);
+    if (!has_interval) {
+        return Result::Err(InspectorError::Command("compare needs at least two different quantiles [from -q] to bound its predictive intervals".to_owned()));
+    }
+    Result::Ok(Command::Contextual(ContextualCommand::Compare(options, expr_text.to_owned())))
+}
+
+pub fn parse_fit_report(fit_report_body_text : &str) -> Result<Command, InspectorError> {
+    let (options, rest) = parse_simulation_options(fit_report_body_text, FIT_REPORT_FLAGS)?;
+    if (!rest.is_empty()) {
+        return Result::Err(InspectorError::Command(format!("fit_report only takes flags, but was also given {}", rest)));
+    }
+    if (options.maybe_num_samples == Option::Some(1)) {
+        return Result::Err(InspectorError::Command("fit_report needs at least two samples per application for its predictive variances".to_owned()));
+    }
+    Result::Ok(Command::Contextual(ContextualCommand::FitReport(options)))
+}
+
+pub fn parse_trace(trace_body_text : &str) -> Result<Command, InspectorError> {
+    let (traced_command, untrimmed_expr_text) = trace_body_text.split_once(char::is_whitespace)
+                                                .unwrap_or((trace_body_text, ""));
+    match (traced_command) {
+        "evaluate" | "eval" => {
+            let expr_text = untrimmed_expr_text.trim().to_owned();
+            Result::Ok(Command::Contextual(ContextualCommand::TraceEvaluate(expr_text)))
+        },
+        _ => Result::Err(InspectorError::Command(format!("Cannot trace {}, only eval [expr]", traced_command)))
+    }
+}
+
+pub fn parse_set(set_body_text : &str) -> Result<Command, InspectorError> {
+    let words : Vec<&str> = set_body_text.split_whitespace().collect();
+    match (words.as_slice()) {
+        ["trace", "on"] => Result::Ok(Command::SetTrace(true)),
+        ["trace", "off"] => Result::Ok(Command::SetTrace(false)),
+        _ => Result::Err(InspectorError::Command(format!("Set body {} does not have the format trace [on|off]", set_body_text)))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub message : String,
+    pub span : Span,
+    ///Whether the text was well-formed, but referred to an identifier or primitive name which doesn't exist
+    pub is_resolution : bool
+}
+
+impl ParseError {
+    pub fn new(message : String, span : Span) -> ParseError {
+        ParseError {
+            message,
+            span,
+            is_resolution : false
+        }
+    }
+
+    pub fn resolution(message : String, span : Span) -> ParseError {
+        ParseError {
+            message,
+            span,
+            is_resolution : true
+        }
+    }
+
+    ///Renders the error together with the offending line of the source text,
+    ///with carets underneath the part of the line that the error refers to
+    pub fn render(&self, source : &str) -> String {
+        render_span(&self.message, source, self.span)
+    }
+}
+
+///Renders a message together with the line of the source text containing the given span,
+///with carets underneath the part of the line that the span covers
+pub fn render_span(message : &str, source : &str, span : Span) -> String {
+    let start = span.start.min(source.len());
+    let end = span.end.max(start).min(source.len());
+
+    let line_start = source[..start].rfind('\n').map(|ind| ind + 1).unwrap_or(0);
+    let line_end = source[start..].find('\n').map(|ind| ind + start).unwrap_or(source.len());
+    let line = &source[line_start..line_end];
+
+    let caret_offset = source[line_start..start].chars().count();
+    let caret_len = source[start..end.min(line_end)].chars().count().max(1);
+
+    format!("{}\n    {}\n    {}{}", message, line,
+            " ".repeat(caret_offset), "^".repeat(caret_len))
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.span)
+    }
+}
+
+///Parses the entirety of the given text as a single expression. Primitive names [@name]
+///may only be resolved if a context is provided.
+pub fn parse_expression(text : &str, bindings : &Bindings, maybe_ctxt : Option<&Context>) -> Result<Expression, InspectorError> {
+    parse_expression_with_spans(text, bindings, maybe_ctxt).map(|(expr, _)| expr)
+}
+
+///Like parse_expression, but also returns where each subexpression came from in the text,
+///so that later errors about the expression can point at the offending part of it
+pub fn parse_expression_with_spans(text : &str, bindings : &Bindings,
+                                   maybe_ctxt : Option<&Context>) -> Result<(Expression, ExpressionSpans), InspectorError> {
+    let mut parser = Parser::new(text, bindings, maybe_ctxt);
+    let parse_result = parser.parse_atom().and_then(|parsed| parser.expect_end().map(|_| parsed));
+    parse_result.map_err(|err| {
+        if (err.is_resolution) {
+            InspectorError::resolution(err.message).at_span(text, err.span)
+        } else {
+            InspectorError::parse(text, err)
+        }
+    })
+}
+
+///Recursive-descent parser over the tokens of an expression. Grammar:
+///atom := s_expression | reference | primitive_name | identifier
+///s_expression := ( atom atom atom* )
+///reference := #[type_num][term_index] | #[type_num][vector]
+///primitive_name := @[name] | @[name]:[type_num]
+///term_index := p[num] | n[num]
+///vector := [ ] | [ num (, num)* ]
+pub struct Parser<'a> {
+    source : &'a str,
+    tokens : Vec<Token>,
+    position : usize,
+    bindings : &'a Bindings,
+    maybe_ctxt : Option<&'a Context>
+}
+
+impl <'a> Parser<'a> {
+    pub fn new(source : &'a str, bindings : &'a Bindings, maybe_ctxt : Option<&'a Context>) -> Parser<'a> {
+        Parser {
+            source,
+            tokens : tokenize(source),
+            position : 0,
+            bindings,
+            maybe_ctxt
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_kind(&self) -> Option<TokenKind> {
+        self.peek().map(|token| token.kind)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let result = self.tokens.get(self.position).cloned();
+        if (result.is_some()) {
+            self.position += 1;
+        }
+        result
+    }
+
+    fn end_span(&self) -> Span {
+        Span::point(self.source.len())
+    }
+
+    ///The span from the given offset to the end of the last token consumed
+    fn span_since(&self, start : usize) -> Span {
+        let end = self.position.checked_sub(1).map(|ind| self.tokens[ind].span.end).unwrap_or(start);
+        Span::new(start, end.max(start))
+    }
+
+    fn text_of(&self, token : &Token) -> &'a str {
+        token.text(self.source)
+    }
+
+    fn expect(&mut self, kind : TokenKind, context : &str) -> Result<Token, ParseError> {
+        match (self.advance()) {
+            Option::Some(token) => {
+                if (token.kind == kind) {
+                    Result::Ok(token)
+                } else {
+                    Result::Err(ParseError::new(format!("Expected {} {}, but found {}", kind, context,
+                                                        self.text_of(&token)), token.span))
+                }
+            },
+            Option::None => {
+                Result::Err(ParseError::new(format!("Expected {} {}, but ran out of input", kind, context),
+                                            self.end_span()))
+            }
+        }
+    }
+
+    ///Checks that the token at the current position directly abuts the previous one
+    fn expect_adjacent(&self, previous : &Token, context : &str) -> Result<(), ParseError> {
+        match (self.peek()) {
+            Option::Some(token) if token.span.start == previous.span.end => Result::Ok(()),
+            Option::Some(token) => {
+                let gap = Span::new(previous.span.end, token.span.start);
+                Result::Err(ParseError::new(format!("Unexpected whitespace {}", context), gap))
+            },
+            Option::None => {
+                Result::Err(ParseError::new(format!("Ran out of input {}", context), self.end_span()))
+            }
+        }
+    }
+
+    pub fn expect_end(&mut self) -> Result<(), ParseError> {
+        match (self.peek()) {
+            Option::None => Result::Ok(()),
+            Option::Some(token) => {
+                let trailing = Span::new(token.span.start, self.source.len());
+                Result::Err(ParseError::new("Unexpected trailing input after expression".to_string(), trailing))
+            }
+        }
+    }
+
+    pub fn parse_atom(&mut self) -> Result<(Expression, ExpressionSpans), ParseError> {
+        let token = match (self.peek()) {
+            Option::Some(token) => token.clone(),
+            Option::None => {
+                return Result::Err(ParseError::new("Expected an expression, but ran out of input".to_string(),
+                                                   self.end_span()));
+            }
+        };
+        match (token.kind) {
+            TokenKind::LeftParen => {
+                let (app_expr, spans) = self.parse_s_expression()?;
+                Result::Ok((Expression::App(app_expr), spans))
+            },
+            TokenKind::Pound => {
+                let term_ref = self.parse_reference()?;
+                Result::Ok((Expression::Ref(term_ref), ExpressionSpans::Ref(self.span_since(token.span.start))))
+            },
+            TokenKind::At => {
+                let term_ptr = self.parse_primitive_name()?;
+                Result::Ok((Expression::Ref(TermReference::FuncRef(term_ptr)), ExpressionSpans::Ref(self.span_since(token.span.start))))
+            },
+            TokenKind::Word => {
+                let term_ref = self.parse_identifier()?;
+                Result::Ok((Expression::Ref(term_ref), ExpressionSpans::Ref(token.span)))
+            },
+            _ => {
+                Result::Err(ParseError::new(format!("Expected an expression, but found {}", self.text_of(&token)),
+                                            token.span))
+            }
+        }
+    }
+
+    pub fn parse_s_expression(&mut self) -> Result<(AppExpression, ExpressionSpans), ParseError> {
+        let left_paren = self.expect(TokenKind::LeftParen, "to begin s-expression")?;
+        let mut atom_exprs = Vec::new();
+        loop {
+            match (self.peek_kind()) {
+                Option::None => {
+                    return Result::Err(ParseError::new("Unclosed parenthesis".to_string(), left_paren.span));
+                },
+                Option::Some(TokenKind::RightParen) => {
+                    break;
+                },
+                Option::Some(_) => {
+                    let parsed_atom = self.parse_atom()?;
+                    atom_exprs.push(parsed_atom);
+                }
+            }
+        }
+        let right_paren = self.expect(TokenKind::RightParen, "to end s-expression")?;
+        let full_span = left_paren.span.merge(&right_paren.span);
+
+        build_application(atom_exprs, full_span)
+    }
+
+    pub fn parse_reference(&mut self) -> Result<TermReference, ParseError> {
+        let pound = self.expect(TokenKind::Pound, "to begin reference")?;
+        self.expect_adjacent(&pound, "after '#' in reference")?;
+        let type_token = self.expect(TokenKind::Number, "for type number of reference")?;
+        let type_number = self.parse_natural_number(&type_token, "type number")?;
+
+        self.expect_adjacent(&type_token, "after type number in reference")?;
+        if (self.peek_kind() == Option::Some(TokenKind::LeftBracket)) {
+            let vec = self.parse_vector()?;
+            Result::Ok(TermReference::VecRef(type_number, vec))
+        } else {
+            let term_index = self.parse_term_index()?;
+            let term_ptr = TermPointer {
+                type_id : type_number,
+                index : term_index
+            };
+            Result::Ok(TermReference::FuncRef(term_ptr))
+        }
+    }
+
+    pub fn parse_term_index(&mut self) -> Result<TermIndex, ParseError> {
+        let index_token = self.expect(TokenKind::Word, "for term index [p[num] or n[num]]")?;
+        let index_text = self.text_of(&index_token);
+        let (is_primitive, number_text) = if let Option::Some(rest) = index_text.strip_prefix('p') {
+            (true, rest)
+        } else if let Option::Some(rest) = index_text.strip_prefix('n') {
+            (false, rest)
+        } else {
+            return Result::Err(ParseError::new(format!("Term index {} must begin with p or n", index_text),
+                                               index_token.span));
+        };
+        if (number_text.is_empty() || !number_text.chars().all(|c| c.is_ascii_digit())) {
+            return Result::Err(ParseError::new(format!("Term index {} must be p or n followed by digits", index_text),
+                                               index_token.span));
+        }
+        match (number_text.parse::<usize>()) {
+            Result::Ok(term_number) => {
+                if (is_primitive) {
+                    Result::Ok(TermIndex::Primitive(term_number))
+                } else {
+                    Result::Ok(TermIndex::NonPrimitive(term_number))
+                }
+            },
+            Result::Err(err) => {
+                Result::Err(ParseError::new(format!("Invalid term index {}: {}", index_text, err), index_token.span))
+            }
+        }
+    }
+
+    pub fn parse_vector(&mut self) -> Result<Array1<R32>, ParseError> {
+        let left_bracket = self.expect(TokenKind::LeftBracket, "to begin vector")?;
+        let mut elems = Vec::new();
+        if (self.peek_kind() != Option::Some(TokenKind::RightBracket)) {
+            loop {
+                let elem = self.parse_float()?;
+                elems.push(elem);
+                match (self.advance()) {
+                    Option::Some(token) if token.kind == TokenKind::Comma => {},
+                    Option::Some(token) if token.kind == TokenKind::RightBracket => {
+                        return Result::Ok(Array1::from(elems));
+                    },
+                    Option::Some(token) => {
+                        return Result::Err(ParseError::new(format!("Expected ',' or ']' in vector, but found {}",
+                                                                   self.text_of(&token)), token.span));
+                    },
+                    Option::None => {
+                        return Result::Err(ParseError::new("Unclosed bracket for vector".to_string(),
+                                                           left_bracket.span));
+                    }
+                }
+            }
+        }
+        self.expect(TokenKind::RightBracket, "to end vector")?;
+        Result::Ok(Array1::from(elems))
+    }
+
+    fn parse_float(&mut self) -> Result<R32, ParseError> {
+        let token = self.expect(TokenKind::Number, "for vector element")?;
+        let elem_text = self.text_of(&token);
+        match (elem_text.parse::<f32>()) {
+            Result::Ok(elem) if elem.is_finite() => Result::Ok(r32(elem)),
+            _ => Result::Err(ParseError::new(format!("Malformed float: {}", elem_text), token.span))
+        }
+    }
+
+    fn parse_natural_number(&self, token : &Token, description : &str) -> Result<usize, ParseError> {
+        let number_text = self.text_of(token);
+        match (number_text.parse::<usize>()) {
+            Result::Ok(number) => Result::Ok(number),
+            Result::Err(err) => {
+                Result::Err(ParseError::new(format!("Invalid {} {}: {}", description, number_text, err), token.span))
+            }
+        }
+    }
+
+    pub fn parse_primitive_name(&mut self) -> Result<TermPointer, ParseError> {
+        let at = self.expect(TokenKind::At, "to begin primitive name")?;
+        self.expect_adjacent(&at, "after '@' in primitive name")?;
+        let name_token = self.expect(TokenKind::Word, "for primitive name")?;
+        let name = self.text_of(&name_token);
+
+        let mut full_span = at.span.merge(&name_token.span);
+        let maybe_type_id = if (self.peek_kind() == Option::Some(TokenKind::Colon)) {
+            self.expect_adjacent(&name_token, "before ':' in primitive name")?;
+            let colon = self.expect(TokenKind::Colon, "to begin type qualifier")?;
+            self.expect_adjacent(&colon, "after ':' in primitive name")?;
+            let type_token = self.expect(TokenKind::Number, "for type qualifier of primitive name")?;
+            full_span = full_span.merge(&type_token.span);
+            Option::Some(self.parse_natural_number(&type_token, "type number")?)
+        } else {
+            Option::None
+        };
+
+        match (self.maybe_ctxt) {
+            Option::None => {
+                Result::Err(ParseError::resolution(format!("Cannot resolve primitive name {} without a loaded context", name),
+                                                   full_span))
+            },
+            Option::Some(ctxt) => {
+                resolve_primitive_name(ctxt, name, maybe_type_id).map_err(|err| ParseError::resolution(err.to_string(), full_span))
             }
         }
     }
+
+    pub fn parse_identifier(&mut self) -> Result<TermReference, ParseError> {
+        let token = self.expect(TokenKind::Word, "for identifier")?;
+        let identifier = self.text_of(&token);
+        self.bindings.lookup(identifier).map_err(|err| ParseError::resolution(err.to_string(), token.span))
+    }
 }
 
-pub fn parse_identifier<'a>(text : &'a str, bindings : &Bindings) -> Result<(TermReference, &'a str), String> {
-    let maybe_identifier_end_index = text.find(|c : char| c.is_whitespace() || c == ')');
-    let identifier_end_index = maybe_identifier_end_index.unwrap_or(text.len());
+///Finds the primitive term with the given name, optionally restricted to the given type
+pub fn resolve_primitive_name(ctxt : &Context, name : &str, maybe_type_id : Option<TypeId>) -> Result<TermPointer, InspectorError> {
+    let mut matches = Vec::new();
+    for (type_id, primitive_type_space) in ctxt.primitive_directory.primitive_type_spaces.iter() {
+        if (maybe_type_id.map(|qualifier| qualifier != *type_id).unwrap_or(false)) {
+            continue;
+        }
+        for i in 0..primitive_type_space.terms.len() {
+            if (primitive_type_space.terms[i].get_name() == name) {
+                matches.push(TermPointer {
+                    type_id : *type_id,
+                    index : TermIndex::Primitive(i)
+                });
+            }
+        }
+    }
+    matches.sort_by_key(|term_ptr| term_ptr.type_id);
 
-    let (identifier_content_str, suffix) = text.split_at(identifier_end_index);
-    let term_ref = bindings.lookup(identifier_content_str)?;
-    Result::Ok((term_ref, suffix))
+    match (matches.len()) {
+        0 => match (maybe_type_id) {
+            Option::None => Result::Err(InspectorError::resolution(format!("No primitive term named {}", name))),
+            Option::Some(type_id) => {
+                Result::Err(InspectorError::resolution(format!("No primitive term named {} of type #{}", name, type_id)))
+            }
+        },
+        1 => Result::Ok(matches[0]),
+        _ => {
+            let candidates : Vec<String> = matches.iter().map(format_term_ptr).collect();
+            Result::Err(InspectorError::resolution(format!("Primitive name {} is ambiguous between {}; qualify it with a type, as in @{}:{}",
+                                                           name, candidates.join(", "), name, matches[0].type_id)))
+        }
+    }
 }
 
-pub fn parse_atom<'a>(text : &'a str, bindings : &Bindings) -> Result<(Expression, &'a str), String> {
-    if (text.is_empty()) {
-        return Result::Err("Cannot parse atom: empty text".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text : &str) -> ParseError {
+        match (parse_expression(text, &Bindings::new(), Option::None)) {
+            Result::Err(InspectorError::Parse { error, .. }) => error,
+            Result::Err(err) => panic!("Expected a parse error for {:?}, but got {}", text, err),
+            Result::Ok(_) => panic!("Expected {:?} not to parse", text)
+        }
+    }
+
+    #[test]
+    fn parses_references_and_applications() {
+        let bindings = Bindings::new();
+        match (parse_expression("#3p0", &bindings, Option::None)) {
+            Result::Ok(Expression::Ref(TermReference::FuncRef(term_ptr))) => {
+                assert_eq!(term_ptr.type_id, 3);
+                assert_eq!(term_ptr.index, TermIndex::Primitive(0));
+            },
+            _ => panic!("Expected a function reference")
+        }
+        match (parse_expression("#2[1, -2.5e1]", &bindings, Option::None)) {
+            Result::Ok(Expression::Ref(TermReference::VecRef(type_id, vec))) => {
+                assert_eq!(type_id, 2);
+                assert_eq!(vec, Array1::from(vec![r32(1.0), r32(-25.0)]));
+            },
+            _ => panic!("Expected a vector reference")
+        }
+        assert!(matches!(parse_expression(" ( #3n1 #2[] #2[0] ) ", &bindings, Option::None),
+                         Result::Ok(Expression::App(_))));
+    }
+
+    #[test]
+    fn bare_pound() {
+        let error = parse_error("#");
+        assert_eq!(error.message, "Ran out of input after '#' in reference");
+        assert_eq!(error.span, Span::point(1));
+        assert_eq!(error.render("#"), "Ran out of input after '#' in reference\n    #\n     ^");
+    }
+
+    #[test]
+    fn term_index_without_digits() {
+        let error = parse_error("#3p");
+        assert_eq!(error.message, "Term index p must be p or n followed by digits");
+        assert_eq!(error.span, Span::new(2, 3));
+        assert_eq!(error.render("#3p"), "Term index p must be p or n followed by digits\n    #3p\n      ^");
+    }
+
+    #[test]
+    fn unterminated_vector() {
+        let error = parse_error("#3[1,");
+        assert_eq!(error.message, "Expected number for vector element, but ran out of input");
+        assert_eq!(error.span, Span::point(5));
+        assert_eq!(error.render("#3[1,"), "Expected number for vector element, but ran out of input\n    #3[1,\n         ^");
+    }
+
+    #[test]
+    fn unclosed_parenthesis() {
+        let error = parse_error("(");
+        assert_eq!(error.message, "Unclosed parenthesis");
+        assert_eq!(error.span, Span::new(0, 1));
+        assert_eq!(error.render("("), "Unclosed parenthesis\n    (\n    ^");
+    }
+
+    #[test]
+    fn unbalanced_right_parenthesis() {
+        let error = parse_error(")");
+        assert_eq!(error.message, "Expected an expression, but found )");
+        assert_eq!(error.span, Span::new(0, 1));
+
+        let text = "(#3p0 #4p1))";
+        let error = parse_error(text);
+        assert_eq!(error.message, "Unexpected trailing input after expression");
+        assert_eq!(error.span, Span::new(11, 12));
+        assert_eq!(error.render(text), "Unexpected trailing input after expression\n    (#3p0 #4p1))\n               ^");
     }
-    if (text.starts_with('(')) {
-        let (app_expr, updated_text) = parse_s_expression(text, bindings)?;
-        let expr = Expression::App(app_expr);
-        return Result::Ok((expr, updated_text));
-    } else if (text.starts_with('#')) {
-        let (ref_expr, updated_text) = parse_reference(text)?;
-        let expr = Expression::Ref(ref_expr);
-        return Result::Ok((expr, updated_text));
-    } else {
-        //Must be an identifier
-        let (ref_expr, updated_text) = parse_identifier(text, bindings)?;
-        let expr = Expression::Ref(ref_expr); 
-        return Result::Ok((expr, updated_text));
+
+    #[test]
+    fn non_ascii_identifier_carets_count_characters() {
+        match (parse_expression("(#3p0 héllo)", &Bindings::new(), Option::None)) {
+            Result::Err(InspectorError::Resolution { message, maybe_source_span : Option::Some(source_span) }) => {
+                assert_eq!(source_span.span, Span::new(6, 12));
+                assert_eq!(render_span(&message, &source_span.source, source_span.span),
+                           "No identifier named héllo in scope\n    (#3p0 héllo)\n          ^^^^^");
+            },
+            _ => panic!("Expected a located resolution error")
+        }
+    }
+
+    #[test]
+    fn render_shows_only_the_offending_line() {
+        let text = "(#3p0\n #3p)";
+        let error = parse_error(text);
+        assert_eq!(error.span, Span::new(9, 10));
+        assert_eq!(error.render(text), "Term index p must be p or n followed by digits\n     #3p)\n       ^");
+    }
+
+    #[test]
+    fn command_names_are_unique() {
+        let mut names : Vec<&str> = COMMANDS.iter().flat_map(|command| command.names.iter().cloned()).collect();
+        let num_names = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), num_names);
+    }
+
+    #[test]
+    fn dispatches_through_command_table() {
+        assert!(matches!(parse_command_line("  help "), Result::Ok(Command::Help)));
+        assert!(matches!(parse_command_line("seed 12"), Result::Ok(Command::Seed(Option::Some(12)))));
+        assert!(matches!(parse_command_line("eval (f x)"), Result::Ok(Command::Contextual(ContextualCommand::Evaluate(_)))));
+        assert!(matches!(parse_command_line("help me"), Result::Err(InspectorError::Command(_))));
+        assert!(matches!(parse_command_line("eval"), Result::Err(InspectorError::Command(_))));
+        assert!(matches!(parse_command_line("frobnicate"), Result::Err(InspectorError::Command(_))));
+    }
+
+    #[test]
+    fn quantiles_need_a_sample_count() {
+        assert!(matches!(parse_command_line("sim -q 0.1,0.9 (f x)"), Result::Err(InspectorError::Command(_))));
+        assert!(matches!(parse_command_line("sim --mean -q 0.5 (f x)"), Result::Err(InspectorError::Command(_))));
+        match (parse_command_line("sim -n 10 -q 0.1,0.9 -o out.npy (f x)")) {
+            Result::Ok(Command::Contextual(ContextualCommand::Simulate(options, expr_text))) => {
+                assert_eq!(options.get_quantile_levels(), &[0.1, 0.9]);
+                assert_eq!(options.maybe_output_path.as_deref(), Option::Some("out.npy"));
+                assert_eq!(expr_text, "(f x)");
+            },
+            _ => panic!("Expected a simulate command")
+        }
+        //A single sample can still be written out
+        assert!(matches!(parse_command_line("sim -o out.csv (f x)"), Result::Ok(_)));
+        match (parse_command_line("compare (f x)")) {
+            Result::Ok(Command::Contextual(ContextualCommand::Compare(options, _))) => {
+                assert_eq!(options.get_quantile_levels(), crate::statistics::DEFAULT_QUANTILES);
+            },
+            _ => panic!("Expected a compare command")
+        }
+    }
+
+    #[test]
+    fn never_panics_on_prefixes_or_substrings() {
+        let inputs = ["(#3p0 (#4n12 #2[1.5, -2e3]) @name:7 x)", "#", "#3p", "#3[1,", "(", ")", "#18446744073709551616p0",
+                      "#3p99999999999999999999999", "#2[1e99999]", "@", "@:3", "(é #3ñ0 ☃)", "#3[", "[]", ",", ":"];
+        let bindings = Bindings::new();
+        for input in inputs.iter() {
+            for (start, _) in input.char_indices() {
+                for end in (start..=input.len()).filter(|end| input.is_char_boundary(*end)) {
+                    let text = &input[start..end];
+                    if let Result::Err(err) = parse_expression(text, &bindings, Option::None) {
+                        err.to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_identifier_is_a_located_resolution_error() {
+        match (parse_expression("(#3p0 foo)", &Bindings::new(), Option::None)) {
+            Result::Err(err @ InspectorError::Resolution { .. }) => {
+                assert_eq!(err.to_string(), "No identifier named foo in scope\n    (#3p0 foo)\n          ^^^");
+            },
+            Result::Err(err) => panic!("Expected a resolution error, but got {}", err),
+            Result::Ok(_) => panic!("Expected foo not to resolve")
+        }
+    }
+
+    #[test]
+    fn curried_applications_span_to_their_last_argument() {
+        let (_, spans) = parse_expression_with_spans("(#3p0 #2[] #2[1])", &Bindings::new(), Option::None).ok().unwrap();
+        let expected = ExpressionSpans::App {
+            span : Span::new(0, 17),
+            func_spans : Box::new(ExpressionSpans::App {
+                span : Span::new(0, 10),
+                func_spans : Box::new(ExpressionSpans::Ref(Span::new(1, 5))),
+                arg_spans : Box::new(ExpressionSpans::Ref(Span::new(6, 10)))
+            }),
+            arg_spans : Box::new(ExpressionSpans::Ref(Span::new(11, 16)))
+        };
+        assert_eq!(spans, expected);
     }
 }
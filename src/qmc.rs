@@ -0,0 +1,353 @@
+//Low-discrepancy sequences for quasi-Monte Carlo simulation, all of which
+//produce points in the unit hypercube of however many dimensions they support
+use std::sync::Arc;
+use crate::error::*;
+
+///Number of bits of precision in Sobol points
+const SOBOL_BITS : u32 = 32;
+
+pub trait LowDiscrepancySequence {
+    ///The given coordinate of the point at the given index in the sequence
+    fn get_coordinate(&mut self, index : u64, dim : usize) -> f64;
+
+    ///How many dimensions points have, if there's a limit
+    fn get_max_dims(&self) -> Option<usize> {
+        Option::None
+    }
+}
+
+///The base-b radical inverse of n, which mirrors the base-b digits of n about the decimal point
+pub fn radical_inverse(mut n : u64, base : u64) -> f64 {
+    let inv_base = 1.0 / (base as f64);
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    while (n > 0) {
+        result += ((n % base) as f64) * scale;
+        n /= base;
+        scale *= inv_base;
+    }
+    result
+}
+
+fn is_prime(n : u64) -> bool {
+    if (n < 2) {
+        return false;
+    }
+    let mut divisor = 2;
+    while (divisor * divisor <= n) {
+        if (n.is_multiple_of(divisor)) {
+            return false;
+        }
+        divisor += 1;
+    }
+    true
+}
+
+///The Halton sequence, whose d-th coordinate is the radical inverse in the d-th prime base
+pub struct HaltonSequence {
+    primes : Vec<u64>
+}
+
+impl Default for HaltonSequence {
+    fn default() -> HaltonSequence {
+        HaltonSequence::new()
+    }
+}
+
+impl HaltonSequence {
+    pub fn new() -> HaltonSequence {
+        HaltonSequence {
+            primes : Vec::new()
+        }
+    }
+
+    fn get_prime(&mut self, dim : usize) -> u64 {
+        while (self.primes.len() <= dim) {
+            let mut candidate = self.primes.last().map(|prime| prime + 1).unwrap_or(2);
+            while (!is_prime(candidate)) {
+                candidate += 1;
+            }
+            self.primes.push(candidate);
+        }
+        self.primes[dim]
+    }
+}
+
+impl LowDiscrepancySequence for HaltonSequence {
+    fn get_coordinate(&mut self, index : u64, dim : usize) -> f64 {
+        let base = self.get_prime(dim);
+        radical_inverse(index, base)
+    }
+}
+
+///Where Joe and Kuo publish their Sobol direction numbers
+pub const JOE_KUO_URL : &str = "https://web.maths.unsw.edu.au/~fkuo/sobol/new-joe-kuo-6.21201";
+
+///Direction numbers for every dimension of the Sobol sequence after the first, computed from
+///a table in the format of Joe and Kuo's: a header line, followed by one line per dimension of
+///"d s a m_1 ... m_s", where s is the degree of the dimension's primitive polynomial, the bits of
+///a are the polynomial's interior coefficients, and the m_i are its initial direction numbers
+pub struct SobolDirectionNumbers {
+    ///Indexed by [dimension - 1][bit]
+    direction_numbers : Vec<Vec<u32>>
+}
+
+fn parse_joe_kuo_line(line : &str) -> Result<(u32, u64, Vec<u64>), String> {
+    let numbers = line.split_whitespace()
+                      .map(|word| word.parse::<u64>().map_err(|err| format!("{} is not a number: {}", word, err)))
+                      .collect::<Result<Vec<u64>, String>>()?;
+    if (numbers.len() < 4) {
+        return Result::Err("Expected d, s, a and at least one initial direction number".to_owned());
+    }
+    let degree = numbers[1];
+    let coefficients = numbers[2];
+    let initial_direction_numbers = numbers[3..].to_vec();
+    if (degree == 0 || degree > 63 || initial_direction_numbers.len() as u64 != degree) {
+        return Result::Err(format!("Degree {} doesn't match the {} initial direction numbers", degree,
+                                   initial_direction_numbers.len()));
+    }
+    if (coefficients >= (1u64 << (degree - 1))) {
+        return Result::Err(format!("a = {} has more bits than the degree {} polynomial has interior coefficients",
+                                   coefficients, degree));
+    }
+    for (i, m) in initial_direction_numbers.iter().enumerate() {
+        //The i-th [from 1] initial direction number must be odd, and less than 2^i
+        if (m % 2 == 0 || *m >= (1u64 << (i + 1))) {
+            return Result::Err(format!("m_{} = {} must be odd and less than 2^{}", i + 1, m, i + 1));
+        }
+    }
+    Result::Ok((degree as u32, coefficients, initial_direction_numbers))
+}
+
+///The direction numbers [as fractions of 2^SOBOL_BITS] generated by the given primitive polynomial
+///and initial direction numbers, following Joe and Kuo's recurrence
+fn compute_direction_numbers(degree : u32, coefficients : u64, initial_direction_numbers : &[u64]) -> Vec<u32> {
+    let bits = SOBOL_BITS as usize;
+    let degree = degree as usize;
+    let mut result = vec![0u32; bits];
+    for i in 0..degree.min(bits) {
+        result[i] = (initial_direction_numbers[i] << (bits - i - 1)) as u32;
+    }
+    for i in degree..bits {
+        let mut value = result[i - degree] ^ (result[i - degree] >> degree);
+        for k in 1..degree {
+            if ((coefficients >> (degree - 1 - k)) & 1 == 1) {
+                value ^= result[i - k];
+            }
+        }
+        result[i] = value;
+    }
+    result
+}
+
+impl SobolDirectionNumbers {
+    pub fn parse(text : &str) -> Result<SobolDirectionNumbers, InspectorError> {
+        let mut direction_numbers = Vec::new();
+        //The first line is a header
+        for (line_index, line) in text.lines().enumerate().skip(1) {
+            if (line.trim().is_empty()) {
+                continue;
+            }
+            let (degree, coefficients, initial_direction_numbers) = parse_joe_kuo_line(line)
+                .map_err(|message| InspectorError::Serialization(format!("Sobol direction numbers, line {}: {}",
+                                                                         line_index + 1, message)))?;
+            direction_numbers.push(compute_direction_numbers(degree, coefficients, &initial_direction_numbers));
+        }
+        Result::Ok(SobolDirectionNumbers {
+            direction_numbers
+        })
+    }
+
+    ///The number of dimensions of the Sobol sequence which these direction numbers cover
+    pub fn get_num_dims(&self) -> usize {
+        self.direction_numbers.len() + 1
+    }
+}
+
+///The Sobol sequence, with the van der Corput sequence as its first dimension, and Joe-Kuo
+///direction numbers for the rest. Without any direction numbers, only the first dimension exists.
+pub struct SobolSequence {
+    first_direction_numbers : Vec<u32>,
+    maybe_direction_numbers : Option<Arc<SobolDirectionNumbers>>
+}
+
+impl SobolSequence {
+    pub fn new(maybe_direction_numbers : Option<Arc<SobolDirectionNumbers>>) -> SobolSequence {
+        SobolSequence {
+            first_direction_numbers : (1..=SOBOL_BITS).map(|bit| 1u32 << (SOBOL_BITS - bit)).collect(),
+            maybe_direction_numbers
+        }
+    }
+}
+
+impl LowDiscrepancySequence for SobolSequence {
+    fn get_coordinate(&mut self, index : u64, dim : usize) -> f64 {
+        let direction_numbers = if (dim == 0) {
+                                    &self.first_direction_numbers
+                                } else {
+                                    &self.maybe_direction_numbers.as_ref().unwrap().direction_numbers[dim - 1]
+                                };
+        let mut result : u32 = 0;
+        let mut remaining = index;
+        let mut bit = 0;
+        while (remaining > 0 && bit < direction_numbers.len()) {
+            if (remaining & 1 == 1) {
+                result ^= direction_numbers[bit];
+            }
+            remaining >>= 1;
+            bit += 1;
+        }
+        (result as f64) / ((1u64 << SOBOL_BITS) as f64)
+    }
+
+    fn get_max_dims(&self) -> Option<usize> {
+        Option::Some(self.maybe_direction_numbers.as_ref()
+                         .map(|direction_numbers| direction_numbers.get_num_dims())
+                         .unwrap_or(1))
+    }
+}
+
+///Randomizes a point of a low-discrepancy sequence by the Cranley-Patterson rotation:
+///shifting it by the given offset, and wrapping around the unit interval
+pub fn cranley_patterson_shift(point : f64, shift : f64) -> f64 {
+    (point + shift).fract()
+}
+
+///Inverse of the standard normal CDF, by Acklam's rational approximation
+///[relative error below 1.2e-9]
+pub fn inverse_normal_cdf(p : f64) -> f64 {
+    const A : [f64; 6] = [-3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+                          1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00];
+    const B : [f64; 5] = [-5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+                          6.680131188771972e+01, -1.328068155288572e+01];
+    const C : [f64; 6] = [-7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+                          -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00];
+    const D : [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00,
+                          3.754408661907416e+00];
+    const P_LOW : f64 = 0.02425;
+
+    //The tails are infinite, so points exactly on the boundary are nudged inwards
+    let p = p.clamp(1e-12, 1.0 - 1e-12);
+    if (p < P_LOW) {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) /
+        ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if (p <= 1.0 - P_LOW) {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q /
+        (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        let q = (-2.0 * (1.0 - p).ln()).sqrt();
+        -(((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) /
+        ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual : f64, expected : f64, tolerance : f64) {
+        assert!((actual - expected).abs() < tolerance, "Expected {}, but got {}", expected, actual);
+    }
+
+    //The first rows of Joe and Kuo's new-joe-kuo-6.21201
+    const JOE_KUO_PREFIX : &str = "d       s       a       m_i\n\
+                                   2       1       0       1\n\
+                                   3       2       1       1 3\n\
+                                   4       3       1       1 3 1\n";
+
+    #[test]
+    fn radical_inverse_mirrors_digits() {
+        assert_eq!(radical_inverse(0, 2), 0.0);
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(2, 2), 0.25);
+        assert_eq!(radical_inverse(3, 2), 0.75);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        //5 is 12 in base 3, which mirrors to 0.21
+        assert_close(radical_inverse(5, 3), 2.0 / 3.0 + 1.0 / 9.0, 1e-15);
+    }
+
+    #[test]
+    fn halton_dimensions_use_successive_primes() {
+        let mut halton = HaltonSequence::new();
+        assert_close(halton.get_coordinate(1, 0), 1.0 / 2.0, 1e-15);
+        assert_close(halton.get_coordinate(1, 1), 1.0 / 3.0, 1e-15);
+        assert_close(halton.get_coordinate(1, 2), 1.0 / 5.0, 1e-15);
+        assert_close(halton.get_coordinate(1, 5), 1.0 / 13.0, 1e-15);
+        assert_eq!(halton.get_max_dims(), Option::None);
+    }
+
+    #[test]
+    fn sobol_coordinates_follow_the_direction_numbers() {
+        let direction_numbers = SobolDirectionNumbers::parse(JOE_KUO_PREFIX).ok().unwrap();
+        let mut sobol = SobolSequence::new(Option::Some(Arc::new(direction_numbers)));
+        assert_eq!(sobol.get_max_dims(), Option::Some(4));
+        let points : Vec<Vec<f64>> = (0..5).map(|index| (0..3).map(|dim| sobol.get_coordinate(index, dim)).collect())
+                                           .collect();
+        assert_eq!(points, vec![vec![0.0, 0.0, 0.0],
+                                vec![0.5, 0.5, 0.5],
+                                vec![0.25, 0.75, 0.75],
+                                vec![0.75, 0.25, 0.25],
+                                vec![0.125, 0.625, 0.375]]);
+    }
+
+    #[test]
+    fn sobol_projections_are_stratified() {
+        let direction_numbers = SobolDirectionNumbers::parse(JOE_KUO_PREFIX).ok().unwrap();
+        let mut sobol = SobolSequence::new(Option::Some(Arc::new(direction_numbers)));
+        //Each of the first 2^k points of any one dimension lies in a different interval of width 2^-k
+        for dim in 0..4 {
+            let mut intervals : Vec<u64> = (0..64).map(|index| (sobol.get_coordinate(index, dim) * 64.0) as u64).collect();
+            intervals.sort();
+            assert_eq!(intervals, (0..64).collect::<Vec<u64>>());
+        }
+    }
+
+    #[test]
+    fn sobol_without_direction_numbers_has_one_dimension() {
+        let mut sobol = SobolSequence::new(Option::None);
+        assert_eq!(sobol.get_max_dims(), Option::Some(1));
+        assert_eq!(sobol.get_coordinate(3, 0), 0.75);
+    }
+
+    #[test]
+    fn malformed_joe_kuo_tables_are_rejected() {
+        //An even initial direction number
+        assert!(SobolDirectionNumbers::parse("header\n2 1 0 2\n").is_err());
+        //An initial direction number which is too large
+        assert!(SobolDirectionNumbers::parse("header\n3 2 1 1 5\n").is_err());
+        //Too few initial direction numbers for the degree
+        assert!(SobolDirectionNumbers::parse("header\n3 2 1 1\n").is_err());
+        //Too many bits of interior coefficients
+        assert!(SobolDirectionNumbers::parse("header\n3 2 2 1 3\n").is_err());
+        assert!(SobolDirectionNumbers::parse("header\n2 1 zero 1\n").is_err());
+    }
+
+    #[test]
+    fn inverse_normal_cdf_known_values() {
+        assert_close(inverse_normal_cdf(0.5), 0.0, 1e-9);
+        assert_close(inverse_normal_cdf(0.975), 1.959963984540054, 1e-8);
+        assert_close(inverse_normal_cdf(0.025), -1.959963984540054, 1e-8);
+        assert_close(inverse_normal_cdf(0.8413447460685429), 1.0, 1e-8);
+        //In the tails, where the rational approximation changes
+        assert_close(inverse_normal_cdf(0.001), -3.090232306167813, 1e-8);
+        assert_close(inverse_normal_cdf(0.999), 3.090232306167813, 1e-8);
+        //The boundary is nudged inwards rather than being infinite
+        assert!(inverse_normal_cdf(0.0).is_finite());
+        assert!(inverse_normal_cdf(1.0).is_finite());
+    }
+
+    #[test]
+    fn cranley_patterson_shift_wraps_around() {
+        assert_eq!(cranley_patterson_shift(0.25, 0.5), 0.75);
+        assert_eq!(cranley_patterson_shift(0.75, 0.5), 0.25);
+        assert_eq!(cranley_patterson_shift(0.5, 0.5), 0.0);
+        //Shifting keeps a stratified point set stratified
+        let shift = 0.3;
+        let mut intervals : Vec<u64> = (0..8).map(|i| (cranley_patterson_shift((i as f64) / 8.0, shift) * 8.0) as u64).collect();
+        intervals.sort();
+        assert_eq!(intervals, (0..8).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Read};
+use crate::state::*;
+use crate::commands::*;
+use crate::error::*;
+use crate::helper::*;
+use crate::parse_and_handle_command;
+
+///How deeply source commands may nest, to catch files which (indirectly) source themselves
+pub const MAX_SOURCE_DEPTH : usize = 32;
+
+///Splits the given text into commands, each paired with the [1-based] line it starts on.
+///Like in the REPL, a command continues onto following lines until its parentheses balance.
+///Blank lines and comment lines [starting with #] between commands are skipped, and so are
+///blank lines and comment lines within a command, where a # followed by a digit is instead
+///taken to start a term reference.
+pub fn split_script_commands(text : &str) -> Vec<(usize, String)> {
+    let mut result = Vec::new();
+    let mut maybe_pending : Option<(usize, String)> = Option::None;
+    for (line_index, line) in text.lines().enumerate() {
+        let command = match (maybe_pending.take()) {
+            Option::Some((start_line, mut command)) => {
+                if (is_continuation_comment(line.trim())) {
+                    maybe_pending = Option::Some((start_line, command));
+                    continue;
+                }
+                command.push('\n');
+                command.push_str(line);
+                (start_line, command)
+            },
+            Option::None => {
+                let trimmed_line = line.trim();
+                if (trimmed_line.is_empty() || trimmed_line.starts_with('#')) {
+                    continue;
+                }
+                (line_index + 1, line.to_owned())
+            }
+        };
+        if (paren_depth(&command.1) > 0) {
+            maybe_pending = Option::Some(command);
+        } else {
+            result.push(command);
+        }
+    }
+    //Left for the parser to report as unclosed
+    if let Option::Some(command) = maybe_pending {
+        result.push(command);
+    }
+    result
+}
+
+fn is_continuation_comment(trimmed_line : &str) -> bool {
+    match (trimmed_line.strip_prefix('#')) {
+        Option::Some(after_hash) => !after_hash.starts_with(|c : char| c.is_ascii_digit()),
+        Option::None => trimmed_line.is_empty()
+    }
+}
+
+///Runs each command of the given text, stopping at the first one which fails.
+///The origin names where the text came from, for error messages.
+pub fn run_script(text : &str, origin : &str, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    for (line, command) in split_script_commands(text) {
+        if let Result::Err(err) = parse_and_handle_command(&command, glob_state) {
+            return Result::Err(InspectorError::Script {
+                origin : origin.to_owned(),
+                line,
+                inner : Box::new(err)
+            });
+        }
+    }
+    Result::Ok(())
+}
+
+pub fn run_script_from_path(path : &str, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    let contents = read_from_path(path)?;
+    let text = String::from_utf8(contents).map_err(|err| InspectorError::io(path, format!("Not valid UTF-8: {}", err)))?;
+    run_script(&text, path, glob_state)
+}
+
+///Runs the script at the given path within an ongoing session
+pub fn source_script(path : &str, glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    if (glob_state.source_depth >= MAX_SOURCE_DEPTH) {
+        let message = format!("{} would exceed the maximum nesting depth of {}", path, MAX_SOURCE_DEPTH);
+        return Result::Err(InspectorError::Command(message).with_context("Source"));
+    }
+    glob_state.source_depth += 1;
+    let result = run_script_from_path(path, glob_state);
+    glob_state.source_depth -= 1;
+    result
+}
+
+pub fn run_script_from_stdin(glob_state : &mut GlobalState) -> Result<(), InspectorError> {
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).map_err(|err| InspectorError::io("<stdin>", err))?;
+    run_script(&text, "<stdin>", glob_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(commands : &[(usize, &str)]) -> Vec<(usize, String)> {
+        commands.iter().map(|(line, command)| (*line, command.to_string())).collect()
+    }
+
+    #[test]
+    fn skips_blank_and_comment_lines() {
+        let text = "# nightly run\n\nload_context ctxt.json\n   \neval x\n";
+        assert_eq!(split_script_commands(text), owned(&[(3, "load_context ctxt.json"), (5, "eval x")]));
+    }
+
+    #[test]
+    fn joins_lines_until_parens_balance() {
+        let text = "let y = (f\n  (g x)\n#3p0)\neval y";
+        assert_eq!(split_script_commands(text), owned(&[(1, "let y = (f\n  (g x)\n#3p0)"), (4, "eval y")]));
+    }
+
+    #[test]
+    fn blank_continuation_lines_are_skipped() {
+        let text = "sim -n 10 (f\n\n x)";
+        assert_eq!(split_script_commands(text), owned(&[(1, "sim -n 10 (f\n x)")]));
+    }
+
+    #[test]
+    fn comment_continuation_lines_are_skipped() {
+        let text = "let y = (f\n  # the argument\n  #\n  #3p0)\neval y";
+        assert_eq!(split_script_commands(text), owned(&[(1, "let y = (f\n  #3p0)"), (5, "eval y")]));
+    }
+
+    #[test]
+    fn unclosed_command_at_end_is_kept_for_the_parser() {
+        assert_eq!(split_script_commands("eval x\neval (f"), owned(&[(1, "eval x"), (2, "eval (f")]));
+    }
+
+    #[test]
+    fn excess_right_parens_end_the_command() {
+        assert_eq!(split_script_commands("eval (f x))\neval y"), owned(&[(1, "eval (f x))"), (2, "eval y")]));
+    }
+}
@@ -1,92 +1,441 @@
 use fetish_lib::everything::*;
 use crate::expression::*;
+use crate::error::*;
 use ndarray::*;
 use rand::*;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use crate::qmc::*;
+use ndarray_linalg::{Cholesky, Inverse, UPLO};
+
+///Simulations of the same expression, one per row
+pub struct SampledVectors {
+    pub type_id : TypeId,
+    pub samples : Array2<f32>,
+    ///The number of consecutive rows which were drawn jointly [see TermSampler::get_block_size]
+    pub block_size : usize
+}
+
+///The number of runs in each batch of a parallel simulation. Batches have a fixed size and
+///a random number generator seeded from their index, so that results are reproducible
+///regardless of how many threads the batches are spread across.
+pub const SIMULATION_BATCH_SIZE : usize = 64;
+
+///Determines the vector which stands in for each term during a simulation
+pub trait TermSampler {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError>;
+
+    ///Called before each simulation run of an expression
+    fn begin_run(&mut self) {
+    }
+
+    ///The number of consecutive runs which are drawn jointly [rather than independently],
+    ///and so have to be averaged together before the estimator variance can be computed
+    fn get_block_size(&self) -> usize {
+        1
+    }
+}
+
+///Strategies for drawing the samples which simulations are averaged over
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SamplingStrategy {
+    Plain,
+    Antithetic,
+    Halton,
+    Sobol
+}
+
+pub const SAMPLING_STRATEGY_NAMES : &[&str] = &["plain", "antithetic", "halton", "sobol"];
+
+impl SamplingStrategy {
+    pub fn from_name(name : &str) -> Option<SamplingStrategy> {
+        match (name) {
+            "plain" => Option::Some(SamplingStrategy::Plain),
+            "antithetic" => Option::Some(SamplingStrategy::Antithetic),
+            "halton" => Option::Some(SamplingStrategy::Halton),
+            "sobol" => Option::Some(SamplingStrategy::Sobol),
+            _ => Option::None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match (self) {
+            SamplingStrategy::Plain => "plain",
+            SamplingStrategy::Antithetic => "antithetic",
+            SamplingStrategy::Halton => "halton",
+            SamplingStrategy::Sobol => "sobol"
+        }
+    }
+}
+
+///The number of independently-shifted copies of a low-discrepancy point set which
+///quasi-Monte Carlo runs are split between, so that the estimator variance can be measured
+pub const QMC_REPLICATES : usize = 8;
+
+///Draws a fresh sample from a term's model every time the term is encountered
+pub struct IndependentSampler<'r, R : Rng + ?Sized> {
+    rng : &'r mut R
+}
+
+impl <'r, R : Rng + ?Sized> IndependentSampler<'r, R> {
+    pub fn new(rng : &'r mut R) -> IndependentSampler<'r, R> {
+        IndependentSampler {
+            rng
+        }
+    }
+}
+
+impl <'r, R : Rng + ?Sized> TermSampler for IndependentSampler<'r, R> {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError> {
+        let model = zelf.embedder_state.get_embedding(term_ptr);
+        Result::Ok(model.sample_as_vec(self.rng))
+    }
+}
+
+///Pairs each run with the next, in which every sample x from the first is replaced by its
+///reflection 2m - x about the term's posterior mean m, so that their errors tend to cancel
+pub struct AntitheticSampler<'r, R : Rng + ?Sized> {
+    rng : &'r mut R,
+    ///Samples in the order they were drawn during the last non-reflected run
+    samples : Vec<Array1<f32>>,
+    num_drawn : usize,
+    reflecting : bool,
+    started : bool
+}
+
+impl <'r, R : Rng + ?Sized> AntitheticSampler<'r, R> {
+    pub fn new(rng : &'r mut R) -> AntitheticSampler<'r, R> {
+        AntitheticSampler {
+            rng,
+            samples : Vec::new(),
+            num_drawn : 0,
+            reflecting : false,
+            started : false
+        }
+    }
+}
+
+impl <'r, R : Rng + ?Sized> TermSampler for AntitheticSampler<'r, R> {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError> {
+        let model = zelf.embedder_state.get_embedding(term_ptr);
+        //Runs of the same expression draw their terms in the same order, so the n-th draw
+        //of a reflected run corresponds to the n-th draw of the run before it
+        let result = if (self.reflecting && self.num_drawn < self.samples.len()) {
+                         let mean = model.get_mean_as_vec();
+                         &mean * 2.0 - &self.samples[self.num_drawn]
+                     } else {
+                         let sample = model.sample_as_vec(self.rng);
+                         if (!self.reflecting) {
+                             self.samples.push(sample.clone());
+                         }
+                         sample
+                     };
+        self.num_drawn += 1;
+        Result::Ok(result)
+    }
+
+    fn begin_run(&mut self) {
+        self.reflecting = self.started && !self.reflecting;
+        self.started = true;
+        if (!self.reflecting) {
+            self.samples.clear();
+        }
+        self.num_drawn = 0;
+    }
+
+    fn get_block_size(&self) -> usize {
+        2
+    }
+}
+
+///A term's model, as the map from a matrix Z of independent standard normals to the draw
+///mean + row_factor Z col_factor. The model's draws are matrix normal given the covariance between
+///their rows, which is inverse-Wishart distributed. There's no way to drive that from a normal
+///point, so the row covariance is fixed at its expected value [or at its mode, for models with
+///too few degrees of freedom for the expectation to exist].
+pub struct MatrixNormalFactors {
+    pub mean : Array2<f32>,
+    ///Lower Cholesky factor of the row covariance
+    pub row_factor : Array2<f32>,
+    ///Inverse of the lower Cholesky factor of the column precision, so that its
+    ///transpose times itself is the column covariance
+    pub col_factor : Array2<f32>
+}
+
+impl MatrixNormalFactors {
+    pub fn from_model(model : &NormalInverseWishart) -> Result<MatrixNormalFactors, InspectorError> {
+        MatrixNormalFactors::new(model.mean.view(), model.precision.view(), model.big_v.view(), model.little_v)
+    }
+
+    ///Factors for the model with the given mean, column precision, and inverse-Wishart scale
+    ///matrix and degrees of freedom for the row covariance
+    pub fn new(mean : ArrayView2<f32>, precision : ArrayView2<f32>, big_v : ArrayView2<f32>,
+               little_v : f32) -> Result<MatrixNormalFactors, InspectorError> {
+        let (num_rows, num_cols) = mean.dim();
+        if (big_v.dim() != (num_rows, num_rows) || precision.dim() != (num_cols, num_cols)) {
+            return Result::Err(InspectorError::Simulation(format!("A model with a {}x{} mean has a {:?} scale matrix and a {:?} precision",
+                                                                  num_rows, num_cols, big_v.dim(), precision.dim())));
+        }
+        let expectation_denominator = little_v - (num_rows as f32) - 1.0;
+        let denominator = if (expectation_denominator > 0.0) {
+                              expectation_denominator
+                          } else {
+                              little_v + (num_rows as f32) + 1.0
+                          };
+        let row_covariance = &big_v / denominator;
+        let row_factor = row_covariance.cholesky(UPLO::Lower)
+                         .map_err(|err| InspectorError::Simulation(format!("Row covariance has no Cholesky factor: {}", err)))?;
+        let col_factor = precision.cholesky(UPLO::Lower)
+                         .and_then(|precision_factor| precision_factor.inv())
+                         .map_err(|err| InspectorError::Simulation(format!("Column precision has no inverse Cholesky factor: {}", err)))?;
+        Result::Ok(MatrixNormalFactors {
+            mean : mean.to_owned(),
+            row_factor,
+            col_factor
+        })
+    }
+
+    ///The draw corresponding to the given standard normals, flattened in the same [row-major]
+    ///order as Model::get_mean_as_vec
+    pub fn transform(&self, standard_normals : &Array2<f32>) -> Array1<f32> {
+        let draw = &self.mean + &self.row_factor.dot(standard_normals).dot(&self.col_factor);
+        draw.iter().cloned().collect()
+    }
+}
+
+///Draws samples driven by a low-discrepancy sequence, which cover the space more evenly than
+///independent draws. Each coordinate of a point is mapped to a standard normal, and then through
+///the MatrixNormalFactors of the term's model. Runs are split into QMC_REPLICATES blocks, each of
+///which applies its own Cranley-Patterson shift to the sequence.
+pub struct QuasiMonteCarloSampler<'r, R : Rng + ?Sized, Q : LowDiscrepancySequence> {
+    rng : &'r mut R,
+    sequence : Q,
+    block_size : usize,
+    num_runs : usize,
+    ///The next coordinate of the current point to use
+    coordinate : usize,
+    ///Random shifts for each coordinate, for the current block
+    shifts : Vec<f64>,
+    ///Factors for the models of the terms sampled so far
+    factors : HashMap<TermPointer, MatrixNormalFactors>
+}
+
+impl <'r, R : Rng + ?Sized, Q : LowDiscrepancySequence> QuasiMonteCarloSampler<'r, R, Q> {
+    pub fn new(rng : &'r mut R, sequence : Q, num_samples : usize) -> QuasiMonteCarloSampler<'r, R, Q> {
+        let num_replicates = QMC_REPLICATES.min(num_samples).max(1);
+        let block_size = num_samples.div_ceil(num_replicates);
+        QuasiMonteCarloSampler {
+            rng,
+            sequence,
+            block_size : block_size.max(1),
+            num_runs : 0,
+            coordinate : 0,
+            shifts : Vec::new(),
+            factors : HashMap::new()
+        }
+    }
+
+    fn next_uniform(&mut self) -> Result<f64, InspectorError> {
+        if let Option::Some(max_dims) = self.sequence.get_max_dims() {
+            if (self.coordinate >= max_dims) {
+                return Result::Err(InspectorError::Simulation(format!("The low-discrepancy sequence only has {} dimensions, but a simulation run needs more [for sobol, load Joe-Kuo direction numbers with load_sobol_directions, from {}]",
+                                                                      max_dims, JOE_KUO_URL)));
+            }
+        }
+        while (self.shifts.len() <= self.coordinate) {
+            self.shifts.push(self.rng.gen::<f64>());
+        }
+        //num_runs has already been incremented for the current run
+        let point_index = ((self.num_runs - 1) % self.block_size) as u64;
+        let point = self.sequence.get_coordinate(point_index, self.coordinate);
+        let value = cranley_patterson_shift(point, self.shifts[self.coordinate]);
+        self.coordinate += 1;
+        Result::Ok(value)
+    }
+}
+
+impl <'r, R : Rng + ?Sized, Q : LowDiscrepancySequence> TermSampler for QuasiMonteCarloSampler<'r, R, Q> {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError> {
+        if let Entry::Vacant(entry) = self.factors.entry(term_ptr) {
+            let model = zelf.embedder_state.get_embedding(term_ptr);
+            let factors = MatrixNormalFactors::from_model(&model.data)
+                          .map_err(|err| err.with_context(&format_term_ptr(&term_ptr)))?;
+            entry.insert(factors);
+        }
+        let mut standard_normals = Array2::<f32>::zeros(self.factors[&term_ptr].mean.dim());
+        for standard_normal in standard_normals.iter_mut() {
+            *standard_normal = inverse_normal_cdf(self.next_uniform()?) as f32;
+        }
+        Result::Ok(self.factors[&term_ptr].transform(&standard_normals))
+    }
+
+    fn begin_run(&mut self) {
+        if (self.num_runs.is_multiple_of(self.block_size)) {
+            self.shifts.clear();
+        }
+        self.num_runs += 1;
+        self.coordinate = 0;
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+///Uses each term's posterior mean, so that simulation is deterministic
+pub struct MeanSampler;
+
+impl TermSampler for MeanSampler {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError> {
+        let model = zelf.embedder_state.get_embedding(term_ptr);
+        Result::Ok(model.get_mean_as_vec().to_owned())
+    }
+}
+
+///Samples each distinct term only once per simulation run, and reuses that sample wherever
+///the term occurs, so that every run simulates a single consistent world
+pub struct ConsistentSampler<S : TermSampler> {
+    inner : S,
+    samples : HashMap<TermPointer, Array1<f32>>
+}
+
+impl <S : TermSampler> ConsistentSampler<S> {
+    pub fn new(inner : S) -> ConsistentSampler<S> {
+        ConsistentSampler {
+            inner,
+            samples : HashMap::new()
+        }
+    }
+}
+
+impl <S : TermSampler> TermSampler for ConsistentSampler<S> {
+    fn sample_term(&mut self, zelf : &InterpreterAndEmbedderState, term_ptr : TermPointer) -> Result<Array1<f32>, InspectorError> {
+        if let Option::Some(sample) = self.samples.get(&term_ptr) {
+            return Result::Ok(sample.clone());
+        }
+        let sample = self.inner.sample_term(zelf, term_ptr)?;
+        self.samples.insert(term_ptr, sample.clone());
+        Result::Ok(sample)
+    }
+
+    fn begin_run(&mut self) {
+        self.samples.clear();
+        self.inner.begin_run();
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.inner.get_block_size()
+    }
+}
 
 //All returned typed vectors are fully-expanded [not in the compressed space]
 pub trait SimulatesExpressions {
-    fn simulate_app_expression(&self, app_expr : AppExpression) -> Result<TypedVector, String>;
-    fn simulate_func_expression(&self, func_expr : FuncExpression) -> Result<TypedVector, String>;
-    fn simulate_expression(&self, expr : Expression) -> Result<TypedVector, String>;
+    fn simulate_app_expression<S : TermSampler + ?Sized>(&self, app_expr : AppExpression, sampler : &mut S) -> Result<TypedVector, InspectorError>;
+    fn simulate_func_expression<S : TermSampler + ?Sized>(&self, func_expr : FuncExpression, sampler : &mut S) -> Result<TypedVector, InspectorError>;
+    fn simulate_expression<S : TermSampler + ?Sized>(&self, expr : Expression, sampler : &mut S) -> Result<TypedVector, InspectorError>;
+
+    ///Simulates the expression with every term at its posterior mean, which is deterministic
+    fn simulate_expression_at_mean(&self, expr : Expression) -> Result<TypedVector, InspectorError> {
+        self.simulate_expression(expr, &mut MeanSampler)
+    }
 }
 
 fn expand_compressed_vector<'a>(zelf : &InterpreterAndEmbedderState<'a>, type_id : TypeId,
-                                vec : Array1<f32>) -> TypedVector {
+                                vec : Array1<f32>) -> Result<TypedVector, InspectorError> {
     let ctxt = zelf.get_context();
     if (ctxt.is_vector_type(type_id)) {
-        TypedVector {
+        Result::Ok(TypedVector {
             type_id,
             vec
-        }
+        })
     } else {
-        let embedding_space = zelf.embedder_state.model_spaces.get(&type_id).unwrap();
-        let elaborator_mean = embedding_space.elaborator.get_mean();
-        let full_vec = elaborator_mean.dot(&vec);
-        TypedVector {
-            type_id,
-            vec : full_vec
+        match (zelf.embedder_state.model_spaces.get(&type_id)) {
+            Option::None => {
+                Result::Err(InspectorError::Simulation(format!("No model space for type #{}", type_id)))
+            },
+            Option::Some(embedding_space) => {
+                let elaborator_mean = embedding_space.elaborator.get_mean();
+                let full_vec = elaborator_mean.dot(&vec);
+                Result::Ok(TypedVector {
+                    type_id,
+                    vec : full_vec
+                })
+            }
         }
     }
 }
 
-fn simulate_term_pointer<'a>(zelf : &InterpreterAndEmbedderState<'a>, term_ptr : TermPointer) -> TypedVector {
-    let mut rng = rand::thread_rng();
-    let model = zelf.embedder_state.get_embedding(term_ptr);
+fn simulate_term_pointer<'a, S : TermSampler + ?Sized>(zelf : &InterpreterAndEmbedderState<'a>, term_ptr : TermPointer,
+                                               sampler : &mut S) -> Result<TypedVector, InspectorError> {
+    if (!zelf.embedder_state.has_embedding(term_ptr)) {
+        return Result::Err(InspectorError::Simulation(format!("{} has no embedding [try update_models]",
+                                                              format_term_ptr(&term_ptr))));
+    }
     let type_id = term_ptr.type_id;
-    let vec = model.sample_as_vec(&mut rng);
-    TypedVector {
+    let vec = sampler.sample_term(zelf, term_ptr)?;
+    Result::Ok(TypedVector {
         type_id,
         vec
-    }
+    })
 }
 
-fn simulate_term_reference<'a>(zelf : &InterpreterAndEmbedderState<'a>, term_ref : TermReference) -> TypedVector {
+fn simulate_term_reference<'a, S : TermSampler + ?Sized>(zelf : &InterpreterAndEmbedderState<'a>, term_ref : TermReference,
+                                                 sampler : &mut S) -> Result<TypedVector, InspectorError> {
     match (term_ref) {
-        TermReference::FuncRef(func_ptr) => simulate_term_pointer(zelf, func_ptr),
+        TermReference::FuncRef(func_ptr) => simulate_term_pointer(zelf, func_ptr, sampler),
         TermReference::VecRef(type_id, noisy_vec) => {
             let vec = from_noisy(noisy_vec.view());
-            TypedVector {
+            Result::Ok(TypedVector {
                 type_id,
                 vec
-            }
+            })
         }
     }
 }
 
 impl <'a> SimulatesExpressions for InterpreterAndEmbedderState<'a> {
-    fn simulate_app_expression(&self, app_expr : AppExpression) -> Result<TypedVector, String> {
+    fn simulate_app_expression<S : TermSampler + ?Sized>(&self, app_expr : AppExpression, sampler : &mut S) -> Result<TypedVector, InspectorError> {
         let func_expr = *app_expr.func_expr;
         let arg_expr = *app_expr.arg_expr;
 
         let ctxt = self.get_context();
 
-        let func_vec = self.simulate_func_expression(func_expr)?;
-        let arg_vec = self.simulate_expression(arg_expr)?;
+        let func_vec = self.simulate_func_expression(func_expr, sampler)?;
+        let arg_vec = self.simulate_expression(arg_expr, sampler)?;
 
         let function_space_info = ctxt.get_function_space_info(func_vec.type_id);
         let arg_feat_info = ctxt.get_feature_space_info(arg_vec.type_id);
 
         let ret_type_id = ctxt.get_ret_type_id(func_vec.type_id);
 
-        let func_mat = func_vec.vec.into_shape((function_space_info.get_output_dimensions(),
-                                         function_space_info.get_feature_dimensions())).unwrap();
+        let output_dims = function_space_info.get_output_dimensions();
+        let feature_dims = function_space_info.get_feature_dimensions();
+        let func_vec_len = func_vec.vec.len();
+        let func_mat = func_vec.vec.into_shape((output_dims, feature_dims))
+                       .map_err(|err| InspectorError::Simulation(format!("Cannot reshape sampled function of length {} to {}x{}: {}",
+                                                                         func_vec_len, output_dims, feature_dims, err)))?;
 
         let arg_feats = arg_feat_info.get_features_from_base(arg_vec.vec.view());
+        if (arg_feats.len() != feature_dims) {
+            return Result::Err(InspectorError::Simulation(format!("Argument has {} features, but the function expects {}",
+                                                                  arg_feats.len(), feature_dims)));
+        }
 
         let ret_compressed = func_mat.dot(&arg_feats);
-        let ret = expand_compressed_vector(&self, ret_type_id, ret_compressed);
-        Result::Ok(ret)
+        expand_compressed_vector(&self, ret_type_id, ret_compressed)
     }
 
-    fn simulate_func_expression(&self, func_expr : FuncExpression) -> Result<TypedVector, String> {
+    fn simulate_func_expression<S : TermSampler + ?Sized>(&self, func_expr : FuncExpression, sampler : &mut S) -> Result<TypedVector, InspectorError> {
         match (func_expr) {
-            FuncExpression::Func(term_ptr) => Result::Ok(simulate_term_pointer(&self, term_ptr)),
+            FuncExpression::Func(term_ptr) => simulate_term_pointer(&self, term_ptr, sampler),
             FuncExpression::App(app_expr) => {
                 let formatted_app = format!("{}", &app_expr);
-                let result_vec = self.simulate_app_expression(app_expr)?;
+                let result_vec = self.simulate_app_expression(app_expr, sampler)?;
                 if (self.get_context().is_vector_type(result_vec.type_id)) {
-                    Result::Err(format!("Expected function, but obtained vector from simulating {}", 
-                                            formatted_app))
+                    Result::Err(InspectorError::Simulation(format!("Expected function, but obtained vector from simulating {}", 
+                                                                   formatted_app)))
                 } else {
                     Result::Ok(result_vec)
                 }
@@ -94,10 +443,178 @@ impl <'a> SimulatesExpressions for InterpreterAndEmbedderState<'a> {
         }
     }
 
-    fn simulate_expression(&self, expr : Expression) -> Result<TypedVector, String> {
+    fn simulate_expression<S : TermSampler + ?Sized>(&self, expr : Expression, sampler : &mut S) -> Result<TypedVector, InspectorError> {
         match (expr) {
-            Expression::Ref(term_ref) => Result::Ok(simulate_term_reference(&self, term_ref)),
-            Expression::App(app_expression) => self.simulate_app_expression(app_expression)
+            Expression::Ref(term_ref) => simulate_term_reference(&self, term_ref, sampler),
+            Expression::App(app_expression) => self.simulate_app_expression(app_expression, sampler)
         }
     }
 }
+
+pub fn simulate_samples<'a, S : TermSampler + ?Sized>(zelf : &InterpreterAndEmbedderState<'a>, expr : &Expression,
+                                              num_samples : usize, sampler : &mut S) -> Result<SampledVectors, InspectorError> {
+    let mut rows = Vec::with_capacity(num_samples);
+    let mut type_id = 0;
+    for _ in 0..num_samples {
+        sampler.begin_run();
+        let typed_vector = zelf.simulate_expression(expr.clone(), sampler)?;
+        type_id = typed_vector.type_id;
+        rows.push(typed_vector.vec);
+    }
+    let dims = rows.first().map(|row| row.len()).unwrap_or(0);
+    let mut samples = Array2::<f32>::zeros((num_samples, dims));
+    for (i, row) in rows.iter().enumerate() {
+        samples.row_mut(i).assign(row);
+    }
+    Result::Ok(SampledVectors {
+        type_id,
+        samples,
+        block_size : sampler.get_block_size()
+    })
+}
+
+fn derive_batch_seed(seed : u64, batch_index : usize) -> u64 {
+    seed.wrapping_add((batch_index as u64).wrapping_mul(0x9e3779b97f4a7c15))
+}
+
+///Like simulate_samples, but spreads batches of runs across threads. Each batch gets its own
+///sampler from make_sampler, drawing from a generator seeded with the given seed and the batch's index.
+pub fn simulate_samples_in_parallel<'a, F>(zelf : &InterpreterAndEmbedderState<'a>, expr : &Expression,
+                                           num_samples : usize, seed : u64,
+                                           make_sampler : F) -> Result<SampledVectors, InspectorError>
+    where F : for<'r> Fn(&'r mut StdRng) -> Box<dyn TermSampler + 'r> + Sync {
+    //Samplers may draw consecutive runs jointly, so batches have to consist of whole blocks
+    let block_size = make_sampler(&mut StdRng::seed_from_u64(seed)).get_block_size().max(1);
+    let batch_size = SIMULATION_BATCH_SIZE.div_ceil(block_size) * block_size;
+    let num_batches = num_samples.div_ceil(batch_size);
+
+    let batches = (0..num_batches).into_par_iter()
+                  .map(|batch_index| {
+                      let mut rng = StdRng::seed_from_u64(derive_batch_seed(seed, batch_index));
+                      let mut sampler = make_sampler(&mut rng);
+                      let batch_num_samples = batch_size.min(num_samples - batch_index * batch_size);
+                      simulate_samples(zelf, expr, batch_num_samples, sampler.as_mut())
+                  })
+                  .collect::<Result<Vec<SampledVectors>, InspectorError>>()?;
+
+    let type_id = batches.first().map(|batch| batch.type_id).unwrap_or(0);
+    let dims = batches.first().map(|batch| batch.samples.shape()[1]).unwrap_or(0);
+    let mut samples = Array2::<f32>::zeros((num_samples, dims));
+    let mut start = 0;
+    for batch in batches.iter() {
+        let end = start + batch.samples.shape()[0];
+        samples.slice_mut(s![start..end, ..]).assign(&batch.samples);
+        start = end;
+    }
+    Result::Ok(SampledVectors {
+        type_id,
+        samples,
+        block_size
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_all_close(actual : ArrayView2<f32>, expected : ArrayView2<f32>) {
+        assert_eq!(actual.dim(), expected.dim());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-5, "Expected {}, but got {}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn factors_reproduce_the_column_covariance() {
+        let precision = array![[2.0f32, 1.0], [1.0, 2.0]];
+        let factors = MatrixNormalFactors::new(Array2::zeros((1, 2)).view(), precision.view(),
+                                               array![[1.0f32]].view(), 3.0).ok().unwrap();
+        let col_covariance = factors.col_factor.t().dot(&factors.col_factor);
+        assert_all_close(col_covariance.view(), (array![[2.0f32, -1.0], [-1.0, 2.0]] / 3.0).view());
+    }
+
+    #[test]
+    fn row_covariance_is_fixed_at_its_expectation() {
+        //With 2 rows and 7 degrees of freedom, the expected covariance is the scale matrix over 7 - 2 - 1
+        let big_v = array![[8.0f32, 4.0], [4.0, 8.0]];
+        let factors = MatrixNormalFactors::new(Array2::zeros((2, 1)).view(), array![[1.0f32]].view(),
+                                               big_v.view(), 7.0).ok().unwrap();
+        let row_covariance = factors.row_factor.dot(&factors.row_factor.t());
+        assert_all_close(row_covariance.view(), (&big_v / 4.0).view());
+        assert_eq!(factors.row_factor[[0, 1]], 0.0);
+    }
+
+    #[test]
+    fn row_covariance_falls_back_to_its_mode() {
+        //With 1 row and 1.5 degrees of freedom the expectation doesn't exist, so the mode is over 1.5 + 1 + 1
+        let factors = MatrixNormalFactors::new(Array2::zeros((1, 1)).view(), array![[1.0f32]].view(),
+                                               array![[7.0f32]].view(), 1.5).ok().unwrap();
+        assert_all_close(factors.row_factor.view(), array![[2.0f32.sqrt()]].view());
+    }
+
+    #[test]
+    fn transform_adds_the_scaled_normals_to_the_mean_row_major() {
+        let mean = array![[1.0f32, 2.0], [3.0, 4.0]];
+        //Row covariance 2I [from 4I over 5 - 2 - 1], and column covariance diag(1, 1/4)
+        let factors = MatrixNormalFactors::new(mean.view(), array![[1.0f32, 0.0], [0.0, 4.0]].view(),
+                                               (Array2::<f32>::eye(2) * 4.0).view(), 5.0).ok().unwrap();
+        let draw = factors.transform(&array![[1.0f32, 1.0], [0.0, -2.0]]);
+        let sqrt_2 = 2.0f32.sqrt();
+        let expected = array![1.0 + sqrt_2, 2.0 + sqrt_2 * 0.5, 3.0, 4.0 - sqrt_2];
+        for (a, e) in draw.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-5, "Expected {}, but got {}", expected, draw);
+        }
+    }
+
+    #[test]
+    fn mismatched_shapes_are_an_error() {
+        assert!(MatrixNormalFactors::new(Array2::zeros((2, 3)).view(), Array2::eye(2).view(),
+                                         Array2::eye(2).view(), 5.0).is_err());
+    }
+
+    //A full InterpreterAndEmbedderState can only be built from a context generator library's Context,
+    //so this stands in for one: every expression simulates to its single term's posterior mean, plus
+    //noise which differs between calls unless the sampler is a MeanSampler
+    struct OneTermSimulator {
+        posterior_mean : Array1<f32>,
+        num_calls : std::cell::Cell<usize>
+    }
+
+    impl SimulatesExpressions for OneTermSimulator {
+        fn simulate_app_expression<S : TermSampler + ?Sized>(&self, app_expr : AppExpression, sampler : &mut S) -> Result<TypedVector, InspectorError> {
+            self.simulate_expression(Expression::App(app_expr), sampler)
+        }
+        fn simulate_func_expression<S : TermSampler + ?Sized>(&self, _func_expr : FuncExpression, _sampler : &mut S) -> Result<TypedVector, InspectorError> {
+            Result::Err(InspectorError::Simulation("Only whole expressions are simulated".to_owned()))
+        }
+        fn simulate_expression<S : TermSampler + ?Sized>(&self, _expr : Expression, _sampler : &mut S) -> Result<TypedVector, InspectorError> {
+            self.num_calls.set(self.num_calls.get() + 1);
+            let noise = if (std::any::type_name::<S>() == std::any::type_name::<MeanSampler>()) {
+                            0.0
+                        } else {
+                            self.num_calls.get() as f32
+                        };
+            Result::Ok(TypedVector {
+                type_id : 0,
+                vec : &self.posterior_mean + noise
+            })
+        }
+    }
+
+    #[test]
+    fn simulation_at_mean_is_deterministic_and_gives_the_posterior_mean() {
+        let simulator = OneTermSimulator {
+            posterior_mean : array![1.5f32, -2.0],
+            num_calls : std::cell::Cell::new(0)
+        };
+        let expr = Expression::Ref(TermReference::FuncRef(TermPointer {
+            type_id : 1,
+            index : TermIndex::Primitive(0)
+        }));
+        let first = simulator.simulate_expression_at_mean(expr.clone()).ok().unwrap();
+        let second = simulator.simulate_expression_at_mean(expr).ok().unwrap();
+        assert_eq!(first.vec, array![1.5f32, -2.0]);
+        assert_eq!(second.vec, first.vec);
+        assert_eq!(simulator.num_calls.get(), 2);
+    }
+}
@@ -3,76 +3,200 @@ use crate::loading::*;
 use fetish_lib::everything::*;
 use crate::expression::*;
 use crate::simulate::*;
-use std::collections::HashMap;
+use crate::history::*;
+use crate::error::*;
+use crate::trace::*;
+use crate::typecheck::*;
 use std::mem;
+use std::sync::Arc;
+use crate::qmc::*;
+use rand::prelude::*;
+use std::panic::{self, AssertUnwindSafe};
 
 pub struct GlobalState<'a> {
     pub bindings : Bindings,
     pub lib_handle : ContextDefinitionLibraryHandle<'a>,
-    pub maybe_context_state : Option<ContextState>
+    pub maybe_context_state : Option<ContextState>,
+    pub history : CommandHistory,
+    pub source_depth : usize,
+    ///Whether let and eval print a trace of every application they perform
+    pub trace_evaluation : bool,
+    ///Direction numbers for sobol sampling, which have to be loaded from a published table
+    pub maybe_sobol_directions : Option<Arc<SobolDirectionNumbers>>,
+    ///The seed which rng was last seeded with, so that a session's simulations can be reproduced
+    pub seed : u64,
+    ///Source of all randomness in simulations
+    pub rng : StdRng
 }
 
 pub struct ContextState {
-    pub ctxt : Context,
+    //Borrows from ctxt, so it's declared first in order to be dropped first. Only ever None
+    //while the models are being serialized.
+    maybe_interpreter_and_embedder_state : Option<InterpreterAndEmbedderState<'static>>,
+    //Boxed so that the Context stays put even when the ContextState moves, and private so that
+    //it can't be replaced out from under the models which borrow from it
+    ctxt : Box<Context>,
     pub ctxt_bytes : Vec<u8>,
-    pub interpreter_and_embedder_state : SerializedInterpreterAndEmbedderState
+    //Whether the models were reset after a panic, which hasn't been reported yet
+    models_reset_by_panic : bool
+}
+
+// SAFETY: The 'static lifetime is a lie. Callers must only store the result in a ContextState
+// alongside the boxed Context it borrows from, which keeps it sound because:
+// - ctxt is private and never replaced or mutated after construction, and being boxed, the Context
+//   stays put even when the ContextState moves
+// - maybe_interpreter_and_embedder_state is declared before ctxt, so it's dropped first
+// - the state is only ever handed out through perform_on_models and perform_on_models_ref, whose
+//   closures are generic over the lifetime [for<'b>], so nothing borrowed from it can escape with
+//   the 'static lifetime
+unsafe fn extend_lifetime<'b>(interpreter_and_embedder_state : InterpreterAndEmbedderState<'b>)
+                             -> InterpreterAndEmbedderState<'static> {
+    mem::transmute::<InterpreterAndEmbedderState<'b>, InterpreterAndEmbedderState<'static>>(interpreter_and_embedder_state)
 }
 
 impl ContextState {
     pub fn new(ctxt_bytes : Vec<u8>, ctxt : Context) -> ContextState {
-        let deserialized_interpreter_and_embedder_state = InterpreterAndEmbedderState::new(&ctxt); 
-        let interpreter_and_embedder_state = deserialized_interpreter_and_embedder_state.serialize();
+        let ctxt = Box::new(ctxt);
+        let interpreter_and_embedder_state = unsafe {
+            extend_lifetime(InterpreterAndEmbedderState::new(&ctxt))
+        };
         ContextState {
+            maybe_interpreter_and_embedder_state : Option::Some(interpreter_and_embedder_state),
             ctxt,
             ctxt_bytes,
-            interpreter_and_embedder_state
+            models_reset_by_panic : false
         }
     }
 
-    fn make_empty_interpreter_and_embedder_state() -> SerializedInterpreterAndEmbedderState {
-        let newly_evaluated_terms = NewlyEvaluatedTerms::new();
-        let interpreter_state = SerializedInterpreterState {
-            application_tables : HashMap::new(),
-            type_spaces : HashMap::new()
-        };
-        let embedder_state = SerializedEmbedderState {
-            model_spaces : HashMap::new()
-        };
-        SerializedInterpreterAndEmbedderState {
-            interpreter_state,
-            embedder_state,
-            newly_evaluated_terms
-        }
+    pub fn get_context(&self) -> &Context {
+        &self.ctxt
     }
 
-    pub fn perform_on_models<F, R>(&mut self, func : F) -> R
-           where F : FnOnce(&mut InterpreterAndEmbedderState) -> R {
-
-        let placeholder_interpreter_and_embedder_state = ContextState::make_empty_interpreter_and_embedder_state(); 
+    ///Replaces the models with freshly-initialized ones, for when the current ones were lost to, or may have been corrupted by, a panic
+    fn reset_models(&mut self) {
+        let interpreter_and_embedder_state = unsafe {
+            extend_lifetime(InterpreterAndEmbedderState::new(&self.ctxt))
+        };
+        self.maybe_interpreter_and_embedder_state = Option::Some(interpreter_and_embedder_state);
+        self.models_reset_by_panic = true;
+    }
 
-        let serialized_interpreter_and_embedder_state = mem::replace(&mut self.interpreter_and_embedder_state,
-                                                                     placeholder_interpreter_and_embedder_state);
+    ///Whether the models have been reset after a panic since this was last called
+    pub fn take_models_reset_by_panic(&mut self) -> bool {
+        mem::replace(&mut self.models_reset_by_panic, false)
+    }
 
-        let mut interpreter_and_embedder_state = serialized_interpreter_and_embedder_state.deserialize(&self.ctxt);
+    ///Runs the given function on the models. If it panics, the models may have been left
+    ///half-updated, so they're reset before the panic is passed on.
+    pub fn perform_on_models<F, R>(&mut self, func : F) -> R
+           where F : for<'b> FnOnce(&mut InterpreterAndEmbedderState<'b>) -> R {
+        if (self.maybe_interpreter_and_embedder_state.is_none()) {
+            self.reset_models();
+        }
+        let interpreter_and_embedder_state = self.maybe_interpreter_and_embedder_state.as_mut().unwrap();
+        let maybe_result = panic::catch_unwind(AssertUnwindSafe(|| func(interpreter_and_embedder_state)));
+        match (maybe_result) {
+            Result::Ok(result) => result,
+            Result::Err(panic_payload) => {
+                self.reset_models();
+                panic::resume_unwind(panic_payload);
+            }
+        }
+    }
 
-        let ret = func(&mut interpreter_and_embedder_state);
+    ///Runs the given function on the models without modifying them. Since they can't have
+    ///been left half-updated, they're kept as they are even if the function panics.
+    pub fn perform_on_models_ref<F, R>(&mut self, func : F) -> R
+           where F : for<'b> FnOnce(&InterpreterAndEmbedderState<'b>) -> R {
+        if (self.maybe_interpreter_and_embedder_state.is_none()) {
+            self.reset_models();
+        }
+        func(self.maybe_interpreter_and_embedder_state.as_ref().unwrap())
+    }
 
-        let serialized_interpreter_and_embedder_state = interpreter_and_embedder_state.serialize();
+    ///Serializes the models, which remain loaded afterwards. This is expensive, so it should
+    ///only be done when the models actually need to leave the session [e.g. to be saved].
+    pub fn serialize_models(&mut self) -> Result<Vec<u8>, InspectorError> {
+        if (self.maybe_interpreter_and_embedder_state.is_none()) {
+            self.reset_models();
+        }
+        let interpreter_and_embedder_state = self.maybe_interpreter_and_embedder_state.take().unwrap();
+        let ctxt = &*self.ctxt;
+        //Serialization consumes the live state, so it has to be rebuilt afterwards
+        let maybe_round_trip = panic::catch_unwind(AssertUnwindSafe(move || {
+            let serialized_interpreter_and_embedder_state = interpreter_and_embedder_state.serialize();
+            let maybe_bytes = bincode::serialize(&serialized_interpreter_and_embedder_state);
+            let interpreter_and_embedder_state = unsafe {
+                extend_lifetime(serialized_interpreter_and_embedder_state.deserialize(ctxt))
+            };
+            (maybe_bytes, interpreter_and_embedder_state)
+        }));
+        match (maybe_round_trip) {
+            Result::Ok((maybe_bytes, interpreter_and_embedder_state)) => {
+                self.maybe_interpreter_and_embedder_state = Option::Some(interpreter_and_embedder_state);
+                maybe_bytes.map_err(|err| InspectorError::Serialization(format!("Models: {}", err)))
+            },
+            Result::Err(panic_payload) => {
+                self.reset_models();
+                panic::resume_unwind(panic_payload);
+            }
+        }
+    }
 
-        self.interpreter_and_embedder_state = serialized_interpreter_and_embedder_state;
+    ///Replaces the models with the serialized ones in the given bytes. The current
+    ///models are left as they are if the bytes can't be deserialized.
+    pub fn deserialize_models(&mut self, bytes : &[u8]) -> Result<(), InspectorError> {
+        let serialized_interpreter_and_embedder_state = bincode::deserialize::<SerializedInterpreterAndEmbedderState>(bytes)
+                                                        .map_err(|err| InspectorError::Serialization(format!("Models: {}", err)))?;
+        let interpreter_and_embedder_state = unsafe {
+            extend_lifetime(serialized_interpreter_and_embedder_state.deserialize(&self.ctxt))
+        };
+        self.maybe_interpreter_and_embedder_state = Option::Some(interpreter_and_embedder_state);
+        Result::Ok(())
+    }
 
-        ret
+    pub fn simulate_samples<F>(&mut self, app_expr : &Expression, num_samples : usize, seed : u64,
+                               make_sampler : F) -> Result<SampledVectors, InspectorError>
+        where F : for<'r> Fn(&'r mut StdRng) -> Box<dyn TermSampler + 'r> + Sync {
+        self.perform_on_models_ref(|interpreter_and_embedder_state|
+                                   simulate_samples_in_parallel(interpreter_and_embedder_state, app_expr, num_samples,
+                                                                seed, make_sampler))
     }
 
-    pub fn simulate(&mut self, app_expr : Expression) -> Result<TypedVector, String> {
-        self.perform_on_models(|interpreter_and_embedder_state|
-                               interpreter_and_embedder_state.simulate_expression(app_expr))
+    ///Type checks the expression, including checking that any non-primitive terms it refers to exist
+    pub fn check_parsed_expression(&mut self, expr : &Expression, source : &str, spans : &ExpressionSpans) -> Result<TypeId, InspectorError> {
+        self.perform_on_models_ref(|interpreter_and_embedder_state|
+                                   interpreter_and_embedder_state.check_parsed_expression(expr, source, spans))
     }
 
-    pub fn eval(&mut self, app_expr : Expression) -> Result<TermReference, String> {
+    pub fn eval(&mut self, app_expr : Expression) -> Result<TermReference, InspectorError> {
         self.perform_on_models(|interpreter_and_embedder_state| 
                                 interpreter_and_embedder_state.evaluate_expression(app_expr))
     }
+
+    ///Evaluates the expression, but leaves the newly-evaluated terms as they were beforehand,
+    ///so that the evaluation doesn't feed into the next update_models. Any new terms and
+    ///applications are still added to the type spaces and application tables, though.
+    pub fn eval_without_recording(&mut self, app_expr : Expression) -> Result<TermReference, InspectorError> {
+        self.perform_on_models(|interpreter_and_embedder_state| {
+            let newly_evaluated_terms = mem::replace(&mut interpreter_and_embedder_state.newly_evaluated_terms,
+                                                     NewlyEvaluatedTerms::new());
+            let result = interpreter_and_embedder_state.evaluate_expression(app_expr);
+            interpreter_and_embedder_state.newly_evaluated_terms = newly_evaluated_terms;
+            result
+        })
+    }
+    ///Evaluates the expression, also returning the rendered trace of its applications [if it has any]
+    pub fn trace_eval(&mut self, app_expr : Expression) -> Result<(TermReference, Option<String>), InspectorError> {
+        self.perform_on_models(|interpreter_and_embedder_state| {
+            let mut maybe_trace = Option::None;
+            let result_ref = interpreter_and_embedder_state.evaluate_expression_with_trace(app_expr, Option::Some(&mut maybe_trace))?;
+            let ctxt = interpreter_and_embedder_state.get_context();
+            let maybe_rendered_trace = maybe_trace.map(|trace|
+                                           trace.render(&interpreter_and_embedder_state.interpreter_state, ctxt));
+            Result::Ok((result_ref, maybe_rendered_trace))
+        })
+    }
     pub fn update_models(&mut self) {
         self.perform_on_models(|interpreter_and_embedder_state|
                                {
@@ -90,4 +214,8 @@ impl <'a> GlobalState<'a> {
     pub fn unload_context(&mut self) {
         self.maybe_context_state = Option::None
     }
+    pub fn set_seed(&mut self, seed : u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
 }
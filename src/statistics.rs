@@ -0,0 +1,240 @@
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+use ndarray::*;
+use crate::commands::*;
+use crate::error::*;
+
+pub const DEFAULT_QUANTILES : &[f64] = &[0.05, 0.5, 0.95];
+
+///Per-dimension summary statistics of a collection of sampled vectors
+pub struct SampleSummary {
+    pub num_samples : usize,
+    pub mean : Array1<f64>,
+    pub std_dev : Array1<f64>,
+    ///Variance of the mean as an estimator, computed between blocks of jointly-drawn samples
+    pub estimator_variance : Array1<f64>,
+    pub quantile_levels : Vec<f64>,
+    ///Indexed by [quantile level, dimension]
+    pub quantiles : Array2<f64>
+}
+
+///The q-th quantile of already-sorted values, linearly interpolating between neighbors
+pub fn sorted_quantile(sorted_values : &[f64], q : f64) -> f64 {
+    if (sorted_values.is_empty()) {
+        return f64::NAN;
+    }
+    let position = q.clamp(0.0, 1.0) * ((sorted_values.len() - 1) as f64);
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let fraction = position - (lower as f64);
+    sorted_values[lower] * (1.0 - fraction) + sorted_values[upper] * fraction
+}
+
+///The variance of the mean of the given values, treating the means of consecutive blocks of
+///the given size as independent draws. Undefined for fewer than two blocks.
+pub fn estimate_variance_of_mean(values : &[f64], block_size : usize) -> f64 {
+    let block_means : Vec<f64> = values.chunks(block_size.max(1))
+                                       .map(|block| block.iter().sum::<f64>() / (block.len() as f64))
+                                       .collect();
+    let num_blocks = block_means.len();
+    if (num_blocks < 2) {
+        return f64::NAN;
+    }
+    let mean_of_block_means = block_means.iter().sum::<f64>() / (num_blocks as f64);
+    let sum_squares : f64 = block_means.iter().map(|block_mean| (block_mean - mean_of_block_means).powi(2)).sum();
+    sum_squares / ((num_blocks - 1) as f64) / (num_blocks as f64)
+}
+
+impl SampleSummary {
+    ///Summarizes the given samples, which are the rows of the given matrix. Consecutive samples
+    ///in blocks of the given size are taken to be drawn jointly, and all blocks independently.
+    pub fn new(samples : ArrayView2<f32>, quantile_levels : &[f64], block_size : usize) -> SampleSummary {
+        let num_samples = samples.shape()[0];
+        let dims = samples.shape()[1];
+        let mut mean = Array1::<f64>::zeros(dims);
+        let mut std_dev = Array1::<f64>::zeros(dims);
+        let mut estimator_variance = Array1::<f64>::zeros(dims);
+        let mut quantiles = Array2::<f64>::zeros((quantile_levels.len(), dims));
+
+        for dim in 0..dims {
+            let mut values : Vec<f64> = samples.column(dim).iter().map(|value| *value as f64).collect();
+            let dim_mean = values.iter().sum::<f64>() / (num_samples as f64);
+            //Sample standard deviation, which is undefined for fewer than two samples
+            let dim_std_dev = if (num_samples > 1) {
+                                  let sum_squares : f64 = values.iter().map(|value| (value - dim_mean).powi(2)).sum();
+                                  (sum_squares / ((num_samples - 1) as f64)).sqrt()
+                              } else {
+                                  f64::NAN
+                              };
+            estimator_variance[dim] = estimate_variance_of_mean(&values, block_size);
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            for (i, q) in quantile_levels.iter().enumerate() {
+                quantiles[[i, dim]] = sorted_quantile(&values, *q);
+            }
+            mean[dim] = dim_mean;
+            std_dev[dim] = dim_std_dev;
+        }
+
+        SampleSummary {
+            num_samples,
+            mean,
+            std_dev,
+            estimator_variance,
+            quantile_levels : quantile_levels.to_vec(),
+            quantiles
+        }
+    }
+
+    ///Renders the statistics as a table with one row per dimension
+    pub fn render(&self) -> String {
+        let mut result = String::new();
+        let _ = write!(result, "{:>5} {:>12} {:>12} {:>12}", "dim", "mean", "std", "est_var");
+        for q in self.quantile_levels.iter() {
+            let _ = write!(result, " {:>12}", format!("q{}", q));
+        }
+        for dim in 0..self.mean.len() {
+            let _ = write!(result, "\n{:>5} {:>12.6} {:>12.6} {:>12.6e}", dim, self.mean[dim], self.std_dev[dim],
+                           self.estimator_variance[dim]);
+            for i in 0..self.quantile_levels.len() {
+                let _ = write!(result, " {:>12.6}", self.quantiles[[i, dim]]);
+            }
+        }
+        result
+    }
+}
+
+pub fn samples_to_csv(samples : ArrayView2<f32>) -> String {
+    let dims = samples.shape()[1];
+    let header : Vec<String> = (0..dims).map(|dim| format!("dim{}", dim)).collect();
+    let mut result = header.join(",");
+    result.push('\n');
+    for row in samples.outer_iter() {
+        let values : Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        result.push_str(&values.join(","));
+        result.push('\n');
+    }
+    result
+}
+
+///The magic string, header length and header of an .npy file of little-endian f32s with the given shape
+fn npy_header(num_rows : usize, num_cols : usize) -> Vec<u8> {
+    const MAGIC : &[u8] = b"\x93NUMPY\x01\x00";
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+                             num_rows, num_cols);
+    //The magic, the header length and the header [with its terminating newline]
+    //must take up a multiple of 64 bytes
+    let unpadded_len = MAGIC.len() + 2 + header.len() + 1;
+    let padding = (64 - unpadded_len % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut result = Vec::with_capacity(MAGIC.len() + 2 + header.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    result.extend_from_slice(header.as_bytes());
+    result
+}
+
+///Encodes the samples in numpy's .npy format [version 1.0, little-endian float32, C order]
+pub fn samples_to_npy(samples : ArrayView2<f32>) -> Vec<u8> {
+    let header = npy_header(samples.shape()[0], samples.shape()[1]);
+    let mut result = Vec::with_capacity(header.len() + samples.len() * 4);
+    result.extend_from_slice(&header);
+    for row in samples.outer_iter() {
+        for value in row.iter() {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    result
+}
+
+///Writes the samples to the given path, as .npy if it has that extension, and as CSV otherwise
+pub fn write_samples(path : &str, samples : ArrayView2<f32>) -> Result<(), InspectorError> {
+    let is_npy = Path::new(path).extension().map(|extension| extension == "npy").unwrap_or(false);
+    if (is_npy) {
+        write_to_path(path, &samples_to_npy(samples))
+    } else {
+        write_to_path(path, samples_to_csv(samples).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual : f64, expected : f64) {
+        assert!((actual - expected).abs() < 1e-12, "Expected {}, but got {}", expected, actual);
+    }
+
+    #[test]
+    fn sorted_quantile_interpolates_between_neighbors() {
+        let values = [1.0, 2.0, 3.0, 4.0];
+        assert_close(sorted_quantile(&values, 0.0), 1.0);
+        assert_close(sorted_quantile(&values, 0.25), 1.75);
+        assert_close(sorted_quantile(&values, 0.5), 2.5);
+        assert_close(sorted_quantile(&values, 1.0), 4.0);
+        //Levels outside [0, 1] are clamped
+        assert_close(sorted_quantile(&values, -0.5), 1.0);
+        assert_close(sorted_quantile(&values, 1.5), 4.0);
+        assert_close(sorted_quantile(&[7.0], 0.3), 7.0);
+        assert!(sorted_quantile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn variance_of_mean_is_computed_between_blocks() {
+        //Block means 1.5, 3.5 and 5.5 have sample variance 4, and there are 3 of them
+        assert_close(estimate_variance_of_mean(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2), 4.0 / 3.0);
+        //With blocks of one, this is the sample variance over the number of values
+        assert_close(estimate_variance_of_mean(&[1.0, 2.0, 3.0, 4.0], 1), (5.0 / 3.0) / 4.0);
+        //A trailing partial block counts as a block of its own, so the block means are 2 and 5
+        assert_close(estimate_variance_of_mean(&[1.0, 3.0, 5.0], 2), 2.25);
+        assert!(estimate_variance_of_mean(&[1.0, 2.0], 2).is_nan());
+    }
+
+    #[test]
+    fn summary_of_columns() {
+        let samples = array![[1.0f32, 10.0], [2.0, 20.0], [3.0, 30.0]];
+        let summary = SampleSummary::new(samples.view(), &[0.5], 1);
+        assert_eq!(summary.num_samples, 3);
+        assert_close(summary.mean[0], 2.0);
+        assert_close(summary.mean[1], 20.0);
+        assert_close(summary.std_dev[0], 1.0);
+        assert_close(summary.std_dev[1], 10.0);
+        assert_close(summary.quantiles[[0, 1]], 20.0);
+    }
+
+    #[test]
+    fn npy_header_is_byte_for_byte() {
+        let samples = array![[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        let bytes = samples_to_npy(samples.view());
+
+        let dict = "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }";
+        //The 10 bytes before the header, the 59 of the dict and the newline are padded up to 128
+        let header = format!("{}{}\n", dict, " ".repeat(128 - 10 - dict.len() - 1));
+        let mut expected = b"\x93NUMPY\x01\x00".to_vec();
+        expected.extend_from_slice(&[118, 0]);
+        expected.extend_from_slice(header.as_bytes());
+        for value in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0].iter() {
+            expected.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(header.len(), 118);
+        assert_eq!(bytes, expected);
+        assert_eq!((10 + header.len()) % 64, 0);
+    }
+
+    #[test]
+    fn npy_header_is_aligned_for_any_shape() {
+        //The header only depends on the shape, so large shapes don't need any data
+        for (rows, cols) in [(0, 0), (1, 1), (12345, 7), (3, 1234567), (usize::MAX, usize::MAX)].iter() {
+            let header = npy_header(*rows, *cols);
+            let header_len = u16::from_le_bytes([header[8], header[9]]) as usize;
+            assert_eq!(header.len(), 10 + header_len);
+            assert_eq!(header.len() % 64, 0);
+            assert_eq!(header[header.len() - 1], b'\n');
+            let shape_text = format!("'shape': ({}, {}), }}", rows, cols);
+            assert!(String::from_utf8_lossy(&header).contains(&shape_text));
+        }
+        let samples = Array2::<f32>::zeros((3, 5));
+        assert_eq!(samples_to_npy(samples.view()).len(), npy_header(3, 5).len() + 3 * 5 * 4);
+    }
+}
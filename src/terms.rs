@@ -0,0 +1,87 @@
+use fetish_lib::everything::*;
+use crate::bindings::*;
+use crate::parsers::*;
+use crate::expression::*;
+use crate::typecheck::*;
+use crate::trace::*;
+use crate::error::*;
+
+///Parses the given text as a single term [not an application], checking that it exists
+pub fn parse_term_ref(text : &str, bindings : &Bindings, zelf : &InterpreterAndEmbedderState) -> Result<TermReference, InspectorError> {
+    match (parse_expression_with_spans(text, bindings, Option::Some(zelf.get_context()))?) {
+        (Expression::Ref(term_ref), spans) => {
+            zelf.check_parsed_expression(&Expression::Ref(term_ref.clone()), text, &spans)?;
+            Result::Ok(term_ref)
+        },
+        (Expression::App(_), _) => {
+            Result::Err(InspectorError::Command(format!("{} is an application, but a term was expected", text)))
+        }
+    }
+}
+
+///All function terms of the given type which the interpreter knows of, primitives first
+pub fn get_func_ptrs(state : &InterpreterState, ctxt : &Context, func_type_id : TypeId) -> Vec<TermPointer> {
+    let num_primitives = ctxt.primitive_directory.primitive_type_spaces.get(&func_type_id)
+                             .map(|primitive_type_space| primitive_type_space.terms.len())
+                             .unwrap_or(0);
+    let num_nonprimitives = state.type_spaces.get(&func_type_id)
+                                 .map(|type_space| type_space.get_num_terms())
+                                 .unwrap_or(0);
+    let primitive_ptrs = (0..num_primitives).map(|i| TermPointer {
+                             type_id : func_type_id,
+                             index : TermIndex::Primitive(i)
+                         });
+    let nonprimitive_ptrs = (0..num_nonprimitives).map(|i| TermPointer {
+                                type_id : func_type_id,
+                                index : TermIndex::NonPrimitive(i)
+                            });
+    primitive_ptrs.chain(nonprimitive_ptrs).collect()
+}
+
+///Lists every term of the given type, one per line, as its index followed by its rendering
+pub fn render_term_list(state : &InterpreterState, ctxt : &Context, type_id : TypeId) -> Result<String, InspectorError> {
+    check_type_exists(ctxt, type_id)?;
+    if (ctxt.is_vector_type(type_id)) {
+        return Result::Err(InspectorError::type_error(format!("{} is a vector type, so it has no terms",
+                                                        format_type(ctxt, type_id))));
+    }
+    let func_ptrs = get_func_ptrs(state, ctxt, type_id);
+    if (func_ptrs.is_empty()) {
+        return Result::Ok(format!("{} has no terms", format_type(ctxt, type_id)));
+    }
+    let lines : Vec<String> = func_ptrs.iter()
+                              .map(|func_ptr| format!("{}: {}", format_term_index(&func_ptr.index), func_ptr.display(state)))
+                              .collect();
+    Result::Ok(lines.join("\n"))
+}
+
+///The number of recorded applications with the given term as the function, and as the argument
+pub fn count_applications(state : &InterpreterState, ctxt : &Context, term_ref : &TermReference) -> (usize, usize) {
+    let as_func = match (term_ref) {
+        TermReference::FuncRef(func_ptr) => {
+            state.application_tables.get(&func_ptr.type_id)
+                 .map(|application_table| application_table.get_app_results_with_func(*func_ptr).len())
+                 .unwrap_or(0)
+        },
+        TermReference::VecRef(_, _) => 0
+    };
+    let term_type_id = get_term_ref_type_id(term_ref);
+    let mut as_arg = 0;
+    for (func_type_id, application_table) in state.application_tables.iter() {
+        if let Type::FuncType(arg_type_id, _) = ctxt.get_type(*func_type_id) {
+            if (arg_type_id == term_type_id) {
+                as_arg += application_table.get_app_results_with_arg(term_ref).len();
+            }
+        }
+    }
+    (as_func, as_arg)
+}
+
+pub fn render_term_summary(state : &InterpreterState, ctxt : &Context, term_ref : &TermReference) -> String {
+    let (as_func, as_arg) = count_applications(state, ctxt, term_ref);
+    let mut lines = Vec::new();
+    lines.push(format!("{}: {}", format_term_ref(term_ref), term_ref.display(state)));
+    lines.push(format!("Type: {}", format_type(ctxt, get_term_ref_type_id(term_ref))));
+    lines.push(format!("Applications: {} [{} as function, {} as argument]", as_func + as_arg, as_func, as_arg));
+    lines.join("\n")
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+///A half-open range [start, end) of byte offsets into the text being parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize
+}
+
+impl Span {
+    pub fn new(start : usize, end : usize) -> Span {
+        Span {
+            start,
+            end
+        }
+    }
+    pub fn point(offset : usize) -> Span {
+        Span::new(offset, offset)
+    }
+    pub fn merge(&self, other : &Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Pound,
+    At,
+    Colon,
+    ///A numeric literal, such as a type number or a vector element
+    Number,
+    ///Anything else which isn't whitespace or punctuation: identifiers, primitive names and term indices [p3, n5]
+    Word
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind : TokenKind,
+    pub span : Span
+}
+
+impl Token {
+    pub fn text<'a>(&self, source : &'a str) -> &'a str {
+        &source[self.span.start..self.span.end]
+    }
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match (self) {
+            TokenKind::LeftParen => "'('",
+            TokenKind::RightParen => "')'",
+            TokenKind::LeftBracket => "'['",
+            TokenKind::RightBracket => "']'",
+            TokenKind::Comma => "','",
+            TokenKind::Pound => "'#'",
+            TokenKind::At => "'@'",
+            TokenKind::Colon => "':'",
+            TokenKind::Number => "number",
+            TokenKind::Word => "identifier"
+        };
+        write!(f, "{}", description)
+    }
+}
+
+fn is_delimiter(c : char) -> bool {
+    c.is_whitespace() || punctuation_kind(c).is_some()
+}
+
+fn punctuation_kind(c : char) -> Option<TokenKind> {
+    match (c) {
+        '(' => Option::Some(TokenKind::LeftParen),
+        ')' => Option::Some(TokenKind::RightParen),
+        '[' => Option::Some(TokenKind::LeftBracket),
+        ']' => Option::Some(TokenKind::RightBracket),
+        ',' => Option::Some(TokenKind::Comma),
+        '#' => Option::Some(TokenKind::Pound),
+        '@' => Option::Some(TokenKind::At),
+        ':' => Option::Some(TokenKind::Colon),
+        _ => Option::None
+    }
+}
+
+///Returns the byte length of the numeric literal at the start of the given text, if any.
+///Accepts an optional sign, digits with an optional fractional part, and an optional exponent.
+fn numeric_literal_len(text : &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    if (i < bytes.len() && (bytes[i] == b'-' || bytes[i] == b'+')) {
+        i += 1;
+    }
+    let integral_start = i;
+    while (i < bytes.len() && bytes[i].is_ascii_digit()) {
+        i += 1;
+    }
+    let mut num_digits = i - integral_start;
+    if (i + 1 < bytes.len() && bytes[i] == b'.' && bytes[i + 1].is_ascii_digit()) {
+        i += 1;
+        let fractional_start = i;
+        while (i < bytes.len() && bytes[i].is_ascii_digit()) {
+            i += 1;
+        }
+        num_digits += i - fractional_start;
+    }
+    if (num_digits == 0) {
+        return Option::None;
+    }
+    //Only consume an exponent if it's actually followed by digits, so that 3e isn't a number
+    if (i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E')) {
+        let mut j = i + 1;
+        if (j < bytes.len() && (bytes[j] == b'-' || bytes[j] == b'+')) {
+            j += 1;
+        }
+        let exponent_start = j;
+        while (j < bytes.len() && bytes[j].is_ascii_digit()) {
+            j += 1;
+        }
+        if (j > exponent_start) {
+            i = j;
+        }
+    }
+    Option::Some(i)
+}
+
+///Splits the given text into tokens, skipping whitespace. Never fails: any run of
+///non-punctuation characters which isn't a number becomes a word, and it's up to the
+///parser to decide whether that word makes sense where it appears.
+pub fn tokenize(text : &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    while (offset < text.len()) {
+        let rest = &text[offset..];
+        let current_char = rest.chars().next().unwrap_or(' ');
+        let char_len = current_char.len_utf8();
+
+        if (current_char.is_whitespace()) {
+            offset += char_len;
+            continue;
+        }
+        if let Option::Some(kind) = punctuation_kind(current_char) {
+            tokens.push(Token {
+                kind,
+                span : Span::new(offset, offset + char_len)
+            });
+            offset += char_len;
+            continue;
+        }
+        let word_len = rest.find(is_delimiter).unwrap_or(rest.len());
+        let (kind, token_len) = match (numeric_literal_len(rest)) {
+            //A numeric prefix glued onto trailing letters [3p2] is a number followed by a word
+            Option::Some(number_len) => (TokenKind::Number, number_len),
+            Option::None => (TokenKind::Word, word_len)
+        };
+        tokens.push(Token {
+            kind,
+            span : Span::new(offset, offset + token_len)
+        });
+        offset += token_len;
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_texts(text : &str) -> Vec<(TokenKind, &str)> {
+        tokenize(text).iter().map(|token| (token.kind, token.text(text))).collect()
+    }
+
+    #[test]
+    fn tokenizes_application() {
+        assert_eq!(kinds_and_texts("(#3p0 foo)"),
+                   vec![(TokenKind::LeftParen, "("), (TokenKind::Pound, "#"), (TokenKind::Number, "3"),
+                        (TokenKind::Word, "p0"), (TokenKind::Word, "foo"), (TokenKind::RightParen, ")")]);
+    }
+
+    #[test]
+    fn tokenizes_vector_and_primitive_name() {
+        assert_eq!(kinds_and_texts("#2[1, -2.5] @plus:4"),
+                   vec![(TokenKind::Pound, "#"), (TokenKind::Number, "2"), (TokenKind::LeftBracket, "["),
+                        (TokenKind::Number, "1"), (TokenKind::Comma, ","), (TokenKind::Number, "-2.5"),
+                        (TokenKind::RightBracket, "]"), (TokenKind::At, "@"), (TokenKind::Word, "plus"),
+                        (TokenKind::Colon, ":"), (TokenKind::Number, "4")]);
+    }
+
+    #[test]
+    fn spans_skip_whitespace() {
+        let spans : Vec<Span> = tokenize("  ( x\t)").iter().map(|token| token.span).collect();
+        assert_eq!(spans, vec![Span::new(2, 3), Span::new(4, 5), Span::new(6, 7)]);
+    }
+
+    #[test]
+    fn empty_and_blank_input_have_no_tokens() {
+        assert!(tokenize("").is_empty());
+        assert!(tokenize(" \t\n ").is_empty());
+    }
+
+    #[test]
+    fn numeric_literals() {
+        assert_eq!(numeric_literal_len("42"), Option::Some(2));
+        assert_eq!(numeric_literal_len("-1.5"), Option::Some(4));
+        assert_eq!(numeric_literal_len("+.5"), Option::Some(3));
+        assert_eq!(numeric_literal_len("1e-3,"), Option::Some(4));
+        assert_eq!(numeric_literal_len("2E+10]"), Option::Some(5));
+        assert_eq!(numeric_literal_len("3."), Option::Some(1));
+        assert_eq!(numeric_literal_len("-"), Option::None);
+        assert_eq!(numeric_literal_len("."), Option::None);
+        assert_eq!(numeric_literal_len("p3"), Option::None);
+        assert_eq!(numeric_literal_len(""), Option::None);
+    }
+
+    #[test]
+    fn exponent_without_digits_is_left_for_the_next_token() {
+        assert_eq!(kinds_and_texts("3e"), vec![(TokenKind::Number, "3"), (TokenKind::Word, "e")]);
+        assert_eq!(kinds_and_texts("3e+"), vec![(TokenKind::Number, "3"), (TokenKind::Word, "e+")]);
+    }
+
+    #[test]
+    fn numbers_glued_to_letters_split() {
+        assert_eq!(kinds_and_texts("3p2"), vec![(TokenKind::Number, "3"), (TokenKind::Word, "p2")]);
+    }
+
+    #[test]
+    fn non_ascii_spans_are_byte_offsets() {
+        let text = "(é #3p0)";
+        let tokens = tokenize(text);
+        let spans : Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(spans, vec![Span::new(0, 1), Span::new(1, 3), Span::new(4, 5), Span::new(5, 6),
+                               Span::new(6, 8), Span::new(8, 9)]);
+        assert_eq!(tokens[1].kind, TokenKind::Word);
+        assert_eq!(tokens[1].text(text), "é");
+    }
+
+    #[test]
+    fn non_ascii_whitespace_and_punctuation_lookalikes() {
+        //A non-breaking space is whitespace, and a fullwidth parenthesis is just part of a word
+        assert_eq!(kinds_and_texts("a\u{a0}b"), vec![(TokenKind::Word, "a"), (TokenKind::Word, "b")]);
+        assert_eq!(kinds_and_texts("\u{ff08}x"), vec![(TokenKind::Word, "\u{ff08}x")]);
+    }
+}
@@ -0,0 +1,135 @@
+use fetish_lib::everything::*;
+use crate::expression::*;
+use crate::typecheck::*;
+use crate::error::*;
+
+///Record of a single application performed while evaluating an expression, together
+///with the records for any applications which its function and argument came from
+pub struct EvaluationTrace {
+    pub term_app : TermApplication,
+    pub result_ref : TermReference,
+    pub result_type_id : TypeId,
+    ///Whether the application had to be computed, rather than being found in the application tables
+    pub newly_created : bool,
+    pub maybe_func_trace : Option<Box<EvaluationTrace>>,
+    pub maybe_arg_trace : Option<Box<EvaluationTrace>>
+}
+
+pub fn get_term_ref_type_id(term_ref : &TermReference) -> TypeId {
+    match (term_ref) {
+        TermReference::FuncRef(func_ptr) => func_ptr.type_id,
+        TermReference::VecRef(type_id, _) => *type_id
+    }
+}
+
+impl EvaluationTrace {
+    ///Renders the trace as a tree, with one application per line, and the applications
+    ///which produced a function or argument nested underneath it
+    pub fn render(&self, state : &InterpreterState, ctxt : &Context) -> String {
+        self.render_with(ctxt, &|term_ref| term_ref.display(state))
+    }
+
+    ///Like render, but with terms displayed by the given function
+    fn render_with(&self, types : &dyn DescribesTypes, display_term : &dyn Fn(&TermReference) -> String) -> String {
+        let mut lines = Vec::new();
+        self.render_into(types, display_term, "", "", &mut lines);
+        lines.join("\n")
+    }
+
+    fn render_into(&self, types : &dyn DescribesTypes, display_term : &dyn Fn(&TermReference) -> String,
+                   first_prefix : &str, rest_prefix : &str, lines : &mut Vec<String>) {
+        let provenance = if (self.newly_created) {"new"} else {"existing"};
+        lines.push(format!("{}({} {}) -> {} : {} [{}]", first_prefix,
+                           format_term_ptr(&self.term_app.func_ptr), format_term_ref(&self.term_app.arg_ref),
+                           format_term_ref(&self.result_ref), format_type(types, self.result_type_id), provenance));
+        lines.push(format!("{}    = ({} {}) -> {}", rest_prefix,
+                           display_term(&TermReference::FuncRef(self.term_app.func_ptr)),
+                           display_term(&self.term_app.arg_ref), display_term(&self.result_ref)));
+
+        let children : Vec<(&str, &EvaluationTrace)> =
+            self.maybe_func_trace.iter().map(|trace| ("func: ", trace.as_ref()))
+            .chain(self.maybe_arg_trace.iter().map(|trace| ("arg: ", trace.as_ref())))
+            .collect();
+        for (i, (label, child)) in children.iter().enumerate() {
+            let is_last = (i + 1 == children.len());
+            let branch = if (is_last) {"└─ "} else {"├─ "};
+            let continuation = if (is_last) {"   "} else {"│  "};
+            let child_first_prefix = format!("{}{}{}", rest_prefix, branch, label);
+            let child_rest_prefix = format!("{}{}", rest_prefix, continuation);
+            child.render_into(types, display_term, &child_first_prefix, &child_rest_prefix, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noisy_float::prelude::*;
+
+    struct TestTypes;
+
+    impl DescribesTypes for TestTypes {
+        fn get_num_types(&self) -> usize {
+            3
+        }
+        fn get_type_kind(&self, type_id : TypeId) -> Type {
+            [Type::VecType(1), Type::FuncType(0, 0), Type::FuncType(0, 1)][type_id].clone()
+        }
+        fn display_type(&self, type_id : TypeId) -> String {
+            ["R", "R -> R", "R -> R -> R"][type_id].to_owned()
+        }
+        fn get_num_primitive_terms(&self, _type_id : TypeId) -> usize {
+            1
+        }
+    }
+
+    fn func(type_id : TypeId, index : TermIndex) -> TermPointer {
+        TermPointer {
+            type_id,
+            index
+        }
+    }
+
+    fn scalar(value : f32) -> TermReference {
+        TermReference::VecRef(0, Array1::from(vec![r32(value)]))
+    }
+
+    fn trace(func_ptr : TermPointer, arg_ref : TermReference, result_ref : TermReference, result_type_id : TypeId,
+             maybe_func_trace : Option<EvaluationTrace>, maybe_arg_trace : Option<EvaluationTrace>) -> EvaluationTrace {
+        EvaluationTrace {
+            term_app : TermApplication {
+                func_ptr,
+                arg_ref
+            },
+            result_ref,
+            result_type_id,
+            newly_created : maybe_arg_trace.is_some(),
+            maybe_func_trace : maybe_func_trace.map(Box::new),
+            maybe_arg_trace : maybe_arg_trace.map(Box::new)
+        }
+    }
+
+    #[test]
+    fn nested_applications_render_as_a_tree() {
+        //((#2p0 #0[1]) (#1p0 ((#2p0 #0[1]) #0[2])))
+        let add_one = func(1, TermIndex::NonPrimitive(0));
+        let add_one_trace = || trace(func(2, TermIndex::Primitive(0)), scalar(1.0), TermReference::FuncRef(add_one), 1,
+                                     Option::None, Option::None);
+        let three_trace = trace(add_one, scalar(2.0), scalar(3.0), 0, Option::Some(add_one_trace()), Option::None);
+        let double_trace = trace(func(1, TermIndex::Primitive(0)), scalar(3.0), scalar(6.0), 0, Option::None, Option::Some(three_trace));
+        let root_trace = trace(add_one, scalar(6.0), scalar(7.0), 0, Option::Some(add_one_trace()), Option::Some(double_trace));
+
+        let rendered = root_trace.render_with(&TestTypes, &|term_ref| format!("<{}>", format_term_ref(term_ref)));
+        let expected = vec!["(#1n0 #0[6]) -> #0[7] : #0: R [new]",
+                            "    = (<#1n0> <#0[6]>) -> <#0[7]>",
+                            "├─ func: (#2p0 #0[1]) -> #1n0 : #1: R -> R [existing]",
+                            "│      = (<#2p0> <#0[1]>) -> <#1n0>",
+                            "└─ arg: (#1p0 #0[3]) -> #0[6] : #0: R [new]",
+                            "       = (<#1p0> <#0[3]>) -> <#0[6]>",
+                            "   └─ arg: (#1n0 #0[2]) -> #0[3] : #0: R [existing]",
+                            "          = (<#1n0> <#0[2]>) -> <#0[3]>",
+                            "      └─ func: (#2p0 #0[1]) -> #1n0 : #1: R -> R [existing]",
+                            "             = (<#2p0> <#0[1]>) -> <#1n0>"];
+        assert_eq!(rendered.lines().collect::<Vec<&str>>(), expected);
+    }
+}
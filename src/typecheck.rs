@@ -0,0 +1,368 @@
+use fetish_lib::everything::*;
+use crate::expression::*;
+use crate::error::*;
+
+///What type checking needs to know about the types of a Context [and which the tests can provide
+///without one, since a Context can only come from a context generator library]
+pub trait DescribesTypes {
+    fn get_num_types(&self) -> usize;
+    ///The kind of an existing type
+    fn get_type_kind(&self, type_id : TypeId) -> Type;
+    ///Displays an existing type
+    fn display_type(&self, type_id : TypeId) -> String;
+    fn get_num_primitive_terms(&self, type_id : TypeId) -> usize;
+}
+
+impl DescribesTypes for Context {
+    fn get_num_types(&self) -> usize {
+        self.get_total_num_types()
+    }
+    fn get_type_kind(&self, type_id : TypeId) -> Type {
+        self.get_type(type_id)
+    }
+    fn display_type(&self, type_id : TypeId) -> String {
+        self.get_type(type_id).display(self)
+    }
+    fn get_num_primitive_terms(&self, type_id : TypeId) -> usize {
+        self.primitive_directory.primitive_type_spaces.get(&type_id)
+                                .map(|primitive_type_space| primitive_type_space.terms.len())
+                                .unwrap_or(0)
+    }
+}
+
+pub fn format_type<T : DescribesTypes + ?Sized>(types : &T, type_id : TypeId) -> String {
+    if (type_id < types.get_num_types()) {
+        format!("#{}: {}", type_id, types.display_type(type_id))
+    } else {
+        format!("#{}: <unknown type>", type_id)
+    }
+}
+
+pub fn check_type_exists<T : DescribesTypes + ?Sized>(types : &T, type_id : TypeId) -> Result<(), InspectorError> {
+    if (type_id < types.get_num_types()) {
+        Result::Ok(())
+    } else {
+        Result::Err(InspectorError::type_error(format!("Type #{} does not exist [there are only {} types]",
+                            type_id, types.get_num_types())))
+    }
+}
+
+//What expressions are checked against. Non-primitive terms live in the interpreter state,
+//so they can only be checked to exist if it's available.
+#[derive(Clone, Copy)]
+struct Checker<'a> {
+    types : &'a dyn DescribesTypes,
+    maybe_state : Option<&'a InterpreterState<'a>>
+}
+
+fn check_term_pointer(checker : Checker, term_ptr : &TermPointer) -> Result<TypeId, InspectorError> {
+    let types = checker.types;
+    let type_id = term_ptr.type_id;
+    check_type_exists(types, type_id)?;
+    if let Type::VecType(_) = types.get_type_kind(type_id) {
+        return Result::Err(InspectorError::type_error(format!("{} refers to a term of vector type {}, but only function types have terms",
+                                   format_term_ptr(term_ptr), format_type(types, type_id))));
+    }
+    match (term_ptr.index, checker.maybe_state) {
+        (TermIndex::Primitive(primitive_index), _) => {
+            let num_primitives = types.get_num_primitive_terms(type_id);
+            if (primitive_index >= num_primitives) {
+                return Result::Err(InspectorError::type_error(format!("{} does not exist: type {} has only {} primitive terms",
+                                           format_term_ptr(term_ptr), format_type(types, type_id), num_primitives)));
+            }
+        },
+        (TermIndex::NonPrimitive(nonprimitive_index), Option::Some(state)) => {
+            let num_nonprimitives = state.type_spaces.get(&type_id)
+                                         .map(|type_space| type_space.get_num_terms())
+                                         .unwrap_or(0);
+            if (nonprimitive_index >= num_nonprimitives) {
+                return Result::Err(InspectorError::type_error(format!("{} does not exist: type {} has only {} non-primitive terms",
+                                           format_term_ptr(term_ptr), format_type(types, type_id), num_nonprimitives)));
+            }
+        },
+        (TermIndex::NonPrimitive(_), Option::None) => {}
+    }
+    Result::Ok(type_id)
+}
+
+fn check_term_reference(checker : Checker, term_ref : &TermReference) -> Result<TypeId, InspectorError> {
+    let types = checker.types;
+    match (term_ref) {
+        TermReference::FuncRef(func_ptr) => check_term_pointer(checker, func_ptr),
+        TermReference::VecRef(type_id, vec) => {
+            check_type_exists(types, *type_id)?;
+            match (types.get_type_kind(*type_id)) {
+                Type::VecType(dim) => {
+                    if (vec.len() == dim) {
+                        Result::Ok(*type_id)
+                    } else {
+                        Result::Err(InspectorError::type_error(format!("{} has {} elements, but type {} has dimension {}",
+                                            format_term_ref(term_ref), vec.len(), format_type(types, *type_id), dim)))
+                    }
+                },
+                Type::FuncType(_, _) => {
+                    Result::Err(InspectorError::type_error(format!("{} is a vector literal, but type {} is a function type",
+                                        format_term_ref(term_ref), format_type(types, *type_id))))
+                }
+            }
+        }
+    }
+}
+
+///The text which an expression was parsed from, together with where each of its subexpressions came from
+#[derive(Clone, Copy)]
+struct Origin<'a> {
+    source : &'a str,
+    spans : &'a ExpressionSpans
+}
+
+impl <'a> Origin<'a> {
+    fn locate(&self, err : InspectorError) -> InspectorError {
+        err.at_span(self.source, self.spans.get_span())
+    }
+    fn func(&self) -> Origin<'a> {
+        self.child(true)
+    }
+    fn arg(&self) -> Origin<'a> {
+        self.child(false)
+    }
+    fn child(&self, is_func : bool) -> Origin<'a> {
+        match (self.spans) {
+            ExpressionSpans::App { func_spans, arg_spans, .. } => Origin {
+                source : self.source,
+                spans : if (is_func) { func_spans } else { arg_spans }
+            },
+            //The spans don't match the shape of the expression, so the best we can do is to blame all of it
+            ExpressionSpans::Ref(_) => *self
+        }
+    }
+}
+
+fn locate(maybe_origin : Option<Origin>, err : InspectorError) -> InspectorError {
+    match (maybe_origin) {
+        Option::Some(origin) => origin.locate(err),
+        Option::None => err
+    }
+}
+
+fn check_app_expression(checker : Checker, app_expr : &AppExpression, maybe_origin : Option<Origin>) -> Result<TypeId, InspectorError> {
+    let types = checker.types;
+    let func_type_id = check_func_expression(checker, &app_expr.func_expr, maybe_origin.map(|origin| origin.func()))?;
+    let arg_type_id = check_expression(checker, &app_expr.arg_expr, maybe_origin.map(|origin| origin.arg()))?;
+
+    match (types.get_type_kind(func_type_id)) {
+        Type::VecType(_) => {
+            Result::Err(locate(maybe_origin.map(|origin| origin.func()),
+                               InspectorError::type_error(format!("Cannot apply {} of vector type {} as a function",
+                                                                  &app_expr.func_expr, format_type(types, func_type_id)))))
+        },
+        Type::FuncType(expected_arg_type_id, ret_type_id) => {
+            if (expected_arg_type_id == arg_type_id) {
+                Result::Ok(ret_type_id)
+            } else {
+                Result::Err(locate(maybe_origin.map(|origin| origin.arg()),
+                                   InspectorError::type_error(format!("Type mismatch in {}: {} has type {}, so expected argument of type {}, but {} has type {}",
+                                                                      app_expr, &app_expr.func_expr, format_type(types, func_type_id),
+                                                                      format_type(types, expected_arg_type_id),
+                                                                      &app_expr.arg_expr, format_type(types, arg_type_id)))))
+            }
+        }
+    }
+}
+
+fn check_func_expression(checker : Checker, func_expr : &FuncExpression, maybe_origin : Option<Origin>) -> Result<TypeId, InspectorError> {
+    match (func_expr) {
+        FuncExpression::Func(term_ptr) => check_term_pointer(checker, term_ptr).map_err(|err| locate(maybe_origin, err)),
+        FuncExpression::App(app_expr) => check_app_expression(checker, app_expr, maybe_origin)
+    }
+}
+
+fn check_expression(checker : Checker, expr : &Expression, maybe_origin : Option<Origin>) -> Result<TypeId, InspectorError> {
+    match (expr) {
+        Expression::Ref(term_ref) => check_term_reference(checker, term_ref).map_err(|err| locate(maybe_origin, err)),
+        Expression::App(app_expr) => check_app_expression(checker, app_expr, maybe_origin)
+    }
+}
+
+//Checks that expressions are well-typed without evaluating them, returning their types.
+//Checking against a Context alone can't tell whether non-primitive terms exist, but checking
+//against an InterpreterAndEmbedderState can.
+pub trait TypeChecksExpressions {
+    fn check_app_expression(&self, app_expr : &AppExpression) -> Result<TypeId, InspectorError>;
+    fn check_func_expression(&self, func_expr : &FuncExpression) -> Result<TypeId, InspectorError>;
+    fn check_expression(&self, expr : &Expression) -> Result<TypeId, InspectorError>;
+    ///Like check_expression, but for an expression parsed from the given source with the given spans,
+    ///so that type errors point at the offending subexpression
+    fn check_parsed_expression(&self, expr : &Expression, source : &str, spans : &ExpressionSpans) -> Result<TypeId, InspectorError>;
+}
+
+fn context_checker(ctxt : &Context) -> Checker<'_> {
+    Checker {
+        types : ctxt,
+        maybe_state : Option::None
+    }
+}
+
+impl TypeChecksExpressions for Context {
+    fn check_app_expression(&self, app_expr : &AppExpression) -> Result<TypeId, InspectorError> {
+        check_app_expression(context_checker(self), app_expr, Option::None)
+    }
+
+    fn check_func_expression(&self, func_expr : &FuncExpression) -> Result<TypeId, InspectorError> {
+        check_func_expression(context_checker(self), func_expr, Option::None)
+    }
+
+    fn check_expression(&self, expr : &Expression) -> Result<TypeId, InspectorError> {
+        check_expression(context_checker(self), expr, Option::None)
+    }
+
+    fn check_parsed_expression(&self, expr : &Expression, source : &str, spans : &ExpressionSpans) -> Result<TypeId, InspectorError> {
+        check_expression(context_checker(self), expr, Option::Some(Origin {
+            source,
+            spans
+        }))
+    }
+}
+
+fn state_checker<'a>(zelf : &'a InterpreterAndEmbedderState<'a>) -> Checker<'a> {
+    Checker {
+        types : zelf.get_context(),
+        maybe_state : Option::Some(&zelf.interpreter_state)
+    }
+}
+
+impl <'a> TypeChecksExpressions for InterpreterAndEmbedderState<'a> {
+    fn check_app_expression(&self, app_expr : &AppExpression) -> Result<TypeId, InspectorError> {
+        check_app_expression(state_checker(self), app_expr, Option::None)
+    }
+
+    fn check_func_expression(&self, func_expr : &FuncExpression) -> Result<TypeId, InspectorError> {
+        check_func_expression(state_checker(self), func_expr, Option::None)
+    }
+
+    fn check_expression(&self, expr : &Expression) -> Result<TypeId, InspectorError> {
+        check_expression(state_checker(self), expr, Option::None)
+    }
+
+    fn check_parsed_expression(&self, expr : &Expression, source : &str, spans : &ExpressionSpans) -> Result<TypeId, InspectorError> {
+        check_expression(state_checker(self), expr, Option::Some(Origin {
+            source,
+            spans
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindings::*;
+    use crate::parsers::*;
+    use crate::tokenizer::*;
+
+    //Indexed by type id: the kind, display and number of primitive terms of each type
+    struct TestTypes(Vec<(Type, &'static str, usize)>);
+
+    impl DescribesTypes for TestTypes {
+        fn get_num_types(&self) -> usize {
+            self.0.len()
+        }
+        fn get_type_kind(&self, type_id : TypeId) -> Type {
+            self.0[type_id].0.clone()
+        }
+        fn display_type(&self, type_id : TypeId) -> String {
+            self.0[type_id].1.to_owned()
+        }
+        fn get_num_primitive_terms(&self, type_id : TypeId) -> usize {
+            self.0[type_id].2
+        }
+    }
+
+    fn test_types() -> TestTypes {
+        TestTypes(vec![(Type::VecType(2), "R^2", 0),
+                       (Type::FuncType(0, 0), "R^2 -> R^2", 2),
+                       (Type::FuncType(1, 0), "(R^2 -> R^2) -> R^2", 1)])
+    }
+
+    fn check(types : &TestTypes, text : &str) -> Result<TypeId, InspectorError> {
+        let (expr, spans) = parse_expression_with_spans(text, &Bindings::new(), Option::None).ok().unwrap();
+        let checker = Checker {
+            types,
+            maybe_state : Option::None
+        };
+        check_expression(checker, &expr, Option::Some(Origin {
+            source : text,
+            spans : &spans
+        }))
+    }
+
+    fn type_error(types : &TestTypes, text : &str) -> (String, Span) {
+        match (check(types, text)) {
+            Result::Err(InspectorError::Type { message, maybe_source_span : Option::Some(source_span) }) => {
+                assert_eq!(source_span.source, text);
+                (message, source_span.span)
+            },
+            Result::Err(err) => panic!("Expected a located type error for {:?}, but got {}", text, err),
+            Result::Ok(type_id) => panic!("Expected {:?} not to type check, but it has type {}", text, type_id)
+        }
+    }
+
+    #[test]
+    fn well_typed_applications_have_the_return_type() {
+        let types = test_types();
+        assert_eq!(check(&types, "(#1p0 #0[1, 2])").ok(), Option::Some(0));
+        assert_eq!(check(&types, "(#2p0 #1p1)").ok(), Option::Some(0));
+        assert_eq!(check(&types, "(#1p0 (#2p0 #1p0))").ok(), Option::Some(0));
+        assert_eq!(check(&types, "#1p1").ok(), Option::Some(1));
+    }
+
+    #[test]
+    fn mismatched_arguments_are_located_at_the_argument() {
+        let types = test_types();
+        let text = "(#2p0 (#1p0 #0[1, 2]))";
+        let (message, span) = type_error(&types, text);
+        assert_eq!(&text[span.start..span.end], "(#1p0 #0[1, 2])");
+        assert_eq!(message, "Type mismatch in (#2p0 (#1p0 #0[1, 2])): #2p0 has type #2: (R^2 -> R^2) -> R^2, \
+                             so expected argument of type #1: R^2 -> R^2, but (#1p0 #0[1, 2]) has type #0: R^2");
+    }
+
+    #[test]
+    fn functions_applied_to_the_wrong_type_are_rejected() {
+        let types = test_types();
+        let text = "(#1p0 #1p1)";
+        let (message, span) = type_error(&types, text);
+        assert_eq!(&text[span.start..span.end], "#1p1");
+        assert!(message.contains("expected argument of type #0: R^2"), "{}", message);
+        assert!(message.contains("#1p1 has type #1: R^2 -> R^2"), "{}", message);
+    }
+
+    #[test]
+    fn vectors_applied_as_functions_are_located_at_the_function() {
+        let types = test_types();
+        let text = "(#1p0 ((#1p1 #0[1, 2]) #0[3, 4]))";
+        let (message, span) = type_error(&types, text);
+        assert_eq!(&text[span.start..span.end], "(#1p1 #0[1, 2])");
+        assert_eq!(message, "Cannot apply (#1p1 #0[1, 2]) of vector type #0: R^2 as a function");
+
+        let (message, _) = type_error(&types, "(#0p0 #0[1, 2])");
+        assert_eq!(message, "#0p0 refers to a term of vector type #0: R^2, but only function types have terms");
+    }
+
+    #[test]
+    fn missing_terms_and_types_are_located() {
+        let types = test_types();
+        let text = "(#2p0 #1p2)";
+        let (message, span) = type_error(&types, text);
+        assert_eq!(&text[span.start..span.end], "#1p2");
+        assert_eq!(message, "#1p2 does not exist: type #1: R^2 -> R^2 has only 2 primitive terms");
+
+        let (message, _) = type_error(&types, "#7p0");
+        assert_eq!(message, "Type #7 does not exist [there are only 3 types]");
+    }
+
+    #[test]
+    fn format_type_shows_id_and_display() {
+        let types = test_types();
+        assert_eq!(format_type(&types, 2), "#2: (R^2 -> R^2) -> R^2");
+        assert_eq!(format_type(&types, 3), "#3: <unknown type>");
+    }
+}